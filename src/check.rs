@@ -0,0 +1,75 @@
+//! Contains the preflight checks of the `check` subcommand, which
+//! catch typos in the paths before a long scan.
+use std::fs;
+use std::path::Path;
+
+use crate::commits::run_git;
+use crate::find_repositories;
+use crate::{log, ExternalResources, GenerationData};
+
+/// Checks that git can be run, and that the input directories and the
+/// other files given in the arguments can be read. Prints out a line
+/// for each check, and reports an error if any of them failed.
+pub fn run(gen: &GenerationData, ext: &ExternalResources) {
+    let mut results = Vec::new();
+
+    let git = run_git(Path::new("."), &["--version"]);
+    results.push((
+        String::from("git can be run"),
+        git.map(|version| version.trim().to_string()),
+    ));
+    for path in &find_repositories::expand_globs(&gen.input) {
+        let readable = fs::read_dir(path).map(|_| String::from("readable directory"));
+        results.push((
+            format!("input {}", path.display()),
+            readable.map_err(|err| err.to_string()),
+        ));
+    }
+    for path in gen
+        .fast_export
+        .iter()
+        .filter(|path| *path != Path::new("-"))
+    {
+        results.push((
+            format!("fast-export stream {}", path.display()),
+            check_file(path),
+        ));
+    }
+    let external_files = [
+        ("external head", &ext.external_head),
+        ("external header", &ext.external_header),
+        ("external footer", &ext.external_footer),
+        ("external css", &ext.external_css),
+        ("theme file", &ext.theme_file),
+        ("embedded font", &ext.embed_font),
+        ("author map", &gen.author_map),
+    ];
+    for (name, path) in &external_files {
+        if let Some(path) = path {
+            results.push((format!("{} {}", name, path.display()), check_file(path)));
+        }
+    }
+
+    for (check, result) in &results {
+        match result {
+            Ok(details) => println!("pass: {} ({})", check, details),
+            Err(err) => println!("FAIL: {} ({})", check, err),
+        }
+    }
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    if failed > 0 {
+        log::error_println(&format!(
+            "error: {} of {} checks failed",
+            failed,
+            results.len()
+        ));
+    }
+}
+
+fn check_file(path: &Path) -> Result<String, String> {
+    match fs::File::open(path).and_then(|file| file.metadata()) {
+        Ok(metadata) if metadata.is_file() => Ok(format!("{} bytes", metadata.len())),
+        Ok(_) => Err(String::from("not a file")),
+        Err(err) => Err(err.to_string()),
+    }
+}
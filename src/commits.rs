@@ -1,21 +1,143 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, Utc};
 #[cfg(feature = "rayon")]
 use rayon::prelude::*;
 
-use std::collections::HashSet;
-use std::path::Path;
+use regex::{Regex, RegexBuilder};
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::str::FromStr;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{log, Commit, GenerationData, ProjectMetadata};
+
+/// The delay before the first retry of a failed pull, doubled for
+/// each retry after it.
+static PULL_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The logical commits seen so far, for `--stable-dates`. Rebasing,
+/// amending or cherry-picking a commit gives the copy a new hash and
+/// committer date, but keeps the author, subject and author date,
+/// which is the date the commits are graphed by. So the commits with
+/// the same author, subject and author date are taken to be copies of
+/// the same work, and only the first one is counted.
+#[derive(Default)]
+pub struct LogicalCommits {
+    seen: HashSet<(String, String, i64)>,
+}
+
+impl LogicalCommits {
+    /// Records the commit, returning false if it's a copy of an
+    /// already recorded one.
+    pub fn insert(&mut self, author: &str, subject: &str, date: &DateTime<FixedOffset>) -> bool {
+        (self.seen).insert((author.to_string(), subject.to_string(), date.timestamp()))
+    }
+}
+
+/// Determines how git formats the commit dates.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum GitDateMode {
+    /// The dates in the timezone they were made in.
+    #[default]
+    Iso,
+    /// The dates converted by git into the local timezone (which can
+    /// be set with the TZ environment variable).
+    Local,
+}
+
+impl FromStr for GitDateMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<GitDateMode, String> {
+        match s {
+            "iso" => Ok(GitDateMode::Iso),
+            "local" => Ok(GitDateMode::Local),
+            _ => Err(format!("unknown git date mode: {}", s)),
+        }
+    }
+}
+
+/// Maps the author aliases to canonical names, read from the
+/// `--author-map` file. Each line maps an alias (a name or an email)
+/// to a name, e.g. `jane@old.com = Jane Doe`, and the lines starting
+/// with a # are comments.
+#[derive(Default)]
+pub struct AuthorMap {
+    aliases: HashMap<String, String>,
+}
 
-use crate::{log, ProjectMetadata};
+impl AuthorMap {
+    /// Reads the map from the file. If it can't be read or parsed,
+    /// the error is printed out and an empty map is returned.
+    pub fn from_file(path: &Path) -> AuthorMap {
+        let parse_result = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|s| AuthorMap::parse(&s));
+        match parse_result {
+            Ok(map) => map,
+            Err(err) => {
+                log::error_println(&format!(
+                    "error: could not load the author map {}: {}",
+                    path.display(),
+                    err
+                ));
+                AuthorMap::default()
+            }
+        }
+    }
+
+    fn parse(s: &str) -> Result<AuthorMap, String> {
+        let mut aliases = HashMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((alias, name)) = line.split_once('=') else {
+                return Err(format!("expected alias = Name on line {}", i + 1));
+            };
+            aliases.insert(alias.trim().to_string(), name.trim().to_string());
+        }
+        Ok(AuthorMap { aliases })
+    }
+
+    /// Returns the canonical name of the author, matching the map's
+    /// aliases against both the name and the email.
+    pub fn canonical<'a>(&'a self, name: &'a str, email: &str) -> &'a str {
+        (self.aliases.get(name))
+            .or_else(|| self.aliases.get(email))
+            .map_or(name, String::as_str)
+    }
+}
 
 pub fn find_dates(
-    author: Option<&String>,
-    pull: bool,
-    repos: &HashSet<ProjectMetadata>,
-) -> Vec<(DateTime<Utc>, ProjectMetadata)> {
+    gen: &GenerationData,
+    repos: &[ProjectMetadata],
+    author_map: &AuthorMap,
+) -> Vec<(DateTime<FixedOffset>, Commit)> {
     let commit_count = AtomicU32::new(0);
-    let author_flag = author.as_ref().map(|author| format!("--author={}", author));
+    let excluded_count = AtomicU32::new(0);
+    let copy_count = AtomicU32::new(0);
+    let timings: Mutex<Vec<(Duration, PathBuf)>> = Mutex::new(Vec::new());
+
+    // When counting co-authors, the author filter is applied here
+    // instead of by git, so that it can match the co-authors as well
+    let coauthor_regex = if gen.count_coauthors {
+        match author_regex(gen) {
+            Ok(regex) => regex,
+            Err(err) => {
+                log::error_println(&format!("error: invalid author regex: {}", err));
+                return Vec::new();
+            }
+        }
+    } else {
+        None
+    };
 
     #[cfg(feature = "rayon")]
     let repo_iter = repos.par_iter();
@@ -23,23 +145,52 @@ pub fn find_dates(
     let repo_iter = repos.iter();
 
     let commit_dates = repo_iter.map(|repo| {
-        let mut commit_dates: Vec<(DateTime<Utc>, ProjectMetadata)> = Vec::new();
-        let path = &repo.path;
-
-        if pull {
-            run_git(&path, &["pull", "--all"]);
-        }
+        let mut commit_dates: Vec<(DateTime<FixedOffset>, Commit)> = Vec::new();
+        let commits = pull_and_read_log(gen, &repo.path, &timings);
+        let mut logical_commits = LogicalCommits::default();
 
-        let mut args = vec!["log", "--all", "--format=format:%ai", "--date=iso8601"];
-        if let Some(author_flag) = &author_flag {
-            args.push(author_flag);
-        }
-        let commits = run_git(&path, &args);
+        for record in commits.split(record_separator(gen)) {
+            let Some(record) = LogRecord::parse(record) else {
+                // The splitting leaves empty records at the ends
+                if gen.strict && !record.trim().is_empty() {
+                    log::error_println(&format!(
+                        "error: could not parse the date of a commit in {}: {:?}",
+                        repo.path.display(),
+                        record.trim()
+                    ));
+                }
+                continue;
+            };
+            let ident = format!("{} <{}>", record.author, record.email);
+            if let Some(regex) = &coauthor_regex {
+                if !regex.is_match(&ident) && !has_matching_coauthor(record.body, regex) {
+                    continue;
+                }
+            }
+            if gen
+                .exclude_message
+                .iter()
+                .any(|re| re.is_match(record.subject))
+            {
+                excluded_count.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            if gen.stable_dates && !logical_commits.insert(&ident, record.subject, &record.date) {
+                copy_count.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
 
-        for date in commits.lines().filter_map(|date| date.parse().ok()) {
             let count = commit_count.fetch_add(1, Ordering::Relaxed) + 1;
             log::verbose_println(&format!("commits accounted for {}\r", count), true);
-            commit_dates.push((date, repo.clone()));
+            let commit = Commit {
+                project: repo.clone(),
+                author: author_map
+                    .canonical(record.author, record.email)
+                    .to_string(),
+                lines_changed: Some(count_lines_changed(record.numstat))
+                    .filter(|_| gen.commit_size_buckets),
+            };
+            commit_dates.push((record.date, commit));
         }
         commit_dates
     });
@@ -55,6 +206,10 @@ pub fn find_dates(
         a
     });
 
+    print_skipped_counts(
+        excluded_count.load(Ordering::Relaxed),
+        copy_count.load(Ordering::Relaxed),
+    );
     log::verbose_println(
         &format!(
             "counted up {} commits in {} repositories",
@@ -64,15 +219,499 @@ pub fn find_dates(
         false,
     );
 
+    if gen.profile_repos {
+        let mut timings = timings.into_inner().unwrap_or_default();
+        print_slowest_repos(&mut timings);
+    }
+
     commit_dates
 }
 
-fn run_git(work_dir: &Path, args: &[&str]) -> String {
+/// Prints out how many commits were skipped for matching
+/// `--exclude-message` or being copies of others (`--stable-dates`).
+fn print_skipped_counts(excluded_count: u32, copy_count: u32) {
+    if excluded_count > 0 {
+        log::verbose_println(
+            &format!(
+                "excluded {} commits based on their messages",
+                excluded_count
+            ),
+            false,
+        );
+    }
+    if copy_count > 0 {
+        log::verbose_println(
+            &format!(
+                "skipped {} rewritten copies of already counted commits",
+                copy_count
+            ),
+            false,
+        );
+    }
+}
+
+/// Pulls the repository if asked to, and reads its log, recording
+/// how long the git commands took into `timings`.
+fn pull_and_read_log(
+    gen: &GenerationData,
+    path: &Path,
+    timings: &Mutex<Vec<(Duration, PathBuf)>>,
+) -> String {
+    let start = Instant::now();
+    if gen.pull {
+        pull(path, gen.pull_retries);
+    }
+
+    let commits = read_log(gen, path);
+    let elapsed = start.elapsed();
+    log::verbose_println(
+        &format!("read the log of {} in {:?}", path.display(), elapsed),
+        false,
+    );
+    if let Ok(mut timings) = timings.lock() {
+        timings.push((elapsed, path.to_path_buf()));
+    }
+    commits
+}
+
+/// Pulls the repository, retrying up to `retries` times with an
+/// exponentially growing delay in between. If all the attempts fail,
+/// the error is reported and the local commits are used as they are.
+fn pull(path: &Path, retries: u32) {
+    let mut delay = PULL_RETRY_DELAY;
+    for attempt in 0..=retries {
+        let Err(err) = run_git(path, &["pull", "--all"]) else {
+            return;
+        };
+        if attempt == retries {
+            log::error_println(&format!(
+                "error: could not pull {}, using the local commits: {}",
+                path.display(),
+                err
+            ));
+        } else {
+            log::verbose_println(
+                &format!(
+                    "pulling {} failed (attempt {} of {}), retrying in {:?}: {}",
+                    path.display(),
+                    attempt + 1,
+                    retries + 1,
+                    delay,
+                    err
+                ),
+                false,
+            );
+            thread::sleep(delay);
+            delay *= 2;
+        }
+    }
+}
+
+/// Prints out the repositories whose git commands took the longest,
+/// slowest first.
+fn print_slowest_repos(timings: &mut [(Duration, PathBuf)]) {
+    timings.sort_by(|(a, _), (b, _)| b.cmp(a));
+    let total: Duration = timings.iter().map(|(elapsed, _)| *elapsed).sum();
+    log::println(&format!(
+        "spent {:.3?} running git in {} repositories, the slowest were:",
+        total,
+        timings.len()
+    ));
+    for (elapsed, path) in timings.iter().take(10) {
+        log::println(&format!("  {:>10.3?}  {}", elapsed, path.display()));
+    }
+}
+
+/// Builds the regex matching the authors given with `--author`, for
+/// filtering the commits that aren't filtered by git.
+pub fn author_regex(gen: &GenerationData) -> Result<Option<Regex>, regex::Error> {
+    gen.author
+        .as_ref()
+        .map(|author| {
+            RegexBuilder::new(author)
+                .case_insensitive(gen.author_ignore_case)
+                .build()
+        })
+        .transpose()
+}
+
+/// Returns the separator between the records of `read_log`'s output.
+/// The records contain the multiline body when counting co-authors,
+/// or the numstat lines when reading the commit sizes, so they're
+/// separated by \x1e instead of lines.
+fn record_separator(gen: &GenerationData) -> char {
+    if gen.count_coauthors || gen.commit_size_buckets {
+        '\x1e'
+    } else {
+        '\n'
+    }
+}
+
+/// A commit's fields, parsed from a record of `read_log`'s output.
+struct LogRecord<'a> {
+    date: DateTime<FixedOffset>,
+    author: &'a str,
+    email: &'a str,
+    subject: &'a str,
+    /// The message body, if it was read for counting co-authors.
+    body: &'a str,
+    /// The --numstat lines, if they were read for the commit sizes.
+    numstat: &'a str,
+}
+
+impl LogRecord<'_> {
+    /// Parses the record, returning None if it doesn't start with a
+    /// date, like the empty records at the ends of the log.
+    fn parse(record: &str) -> Option<LogRecord<'_>> {
+        // The numstat lines come after the \x1f
+        let (record, numstat) = record.split_once('\x1f').unwrap_or((record, ""));
+        let mut parts = record.trim_start_matches('\n').splitn(5, '\t');
+        let date = parts.next()?.parse().ok()?;
+        Some(LogRecord {
+            date,
+            author: parts.next().unwrap_or(""),
+            email: parts.next().unwrap_or(""),
+            subject: parts.next().unwrap_or(""),
+            body: parts.next().unwrap_or(""),
+            numstat,
+        })
+    }
+}
+
+/// Sums up the added and removed lines of `git log --numstat`'s
+/// lines. The binary files, listed with "-" as the counts, are
+/// skipped.
+fn count_lines_changed(numstat: &str) -> usize {
+    numstat
+        .lines()
+        .flat_map(|line| line.split('\t').take(2))
+        .filter_map(|count| count.parse::<usize>().ok())
+        .sum()
+}
+
+/// Returns true if one of the message's Co-authored-by trailers
+/// matches the regex.
+pub fn has_matching_coauthor(message: &str, regex: &Regex) -> bool {
+    message.lines().any(|line| {
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        key.eq_ignore_ascii_case("co-authored-by") && regex.is_match(value)
+    })
+}
+
+/// Runs `git log` in the repository, returning a line with the date,
+/// author and subject of each commit that should be counted. When
+/// counting co-authors, the author's identity and the message body
+/// are included as well, and the commits are separated by \x1e.
+fn read_log(gen: &GenerationData, path: &Path) -> String {
+    let author_flag = gen
+        .author
+        .as_ref()
+        .filter(|_| !gen.count_coauthors)
+        .map(|author| format!("--author={}", author));
+    let max_count_flag = gen
+        .max_commits_per_repo
+        .map(|max_count| format!("--max-count={}", max_count));
+
+    let branch = if gen.current_branch_only || gen.first_parent {
+        default_branch(path)
+    } else {
+        String::from("--all")
+    };
+
+    // The date, author, email and subject are separated by tabs
+    // (%x09). The author and email are the ones from .mailmap, if the
+    // repository has one. In the local mode, git converts the dates
+    // (%ad) into the local timezone according to --date.
+    let (date, date_flag) = match gen.git_date_mode {
+        GitDateMode::Iso => ("%ai", "--date=iso8601"),
+        GitDateMode::Local => ("%ad", "--date=iso-local"),
+    };
+    let format = if gen.commit_size_buckets {
+        // The numstat lines are printed after each commit's line, so
+        // the records are started with \x1e and the numstat is
+        // separated from the body by \x1f
+        let body = if gen.count_coauthors { "%x09%b" } else { "" };
+        format!(
+            "--format=format:%x1e{}%x09%aN%x09%aE%x09%s{}%x1f",
+            date, body
+        )
+    } else if gen.count_coauthors {
+        format!("--format=format:{}%x09%aN%x09%aE%x09%s%x09%b%x1e", date)
+    } else {
+        format!("--format=format:{}%x09%aN%x09%aE%x09%s", date)
+    };
+    let mut args = vec!["log", &branch, &format, date_flag];
+    if gen.commit_size_buckets {
+        // Without the rename detection (e.g. with diff.renames set to
+        // false), a moved file would count as deleting and adding it
+        args.extend(&["--numstat", "--find-renames"]);
+    }
+    if let Some(author_flag) = &author_flag {
+        args.push(author_flag);
+        if gen.author_ignore_case {
+            args.push("--regexp-ignore-case");
+        }
+    }
+    if gen.first_parent {
+        args.push("--first-parent");
+    }
+    if let Some(max_count_flag) = &max_count_flag {
+        args.push(max_count_flag);
+    }
+    // The passthrough arguments go last, so that pathspecs can be
+    // given after a --
+    args.extend(gen.git_arg.iter().map(String::as_str));
+    let commits = match run_git(path, &args) {
+        Ok(commits) => commits,
+        Err(err) => {
+            log::error_println(&format!(
+                "error: could not read the commits of {}: {}",
+                path.display(),
+                err
+            ));
+            String::new()
+        }
+    };
+    if let Some(max_count) = gen.max_commits_per_repo {
+        let records = (commits.split(record_separator(gen)))
+            .filter_map(LogRecord::parse)
+            .count();
+        if records >= max_count {
+            log::verbose_println(
+                &format!(
+                    "only counted the {} most recent commits of {}",
+                    max_count,
+                    path.display()
+                ),
+                false,
+            );
+        }
+    }
+    commits
+}
+
+/// Returns the default branch of the repository, as pointed to by
+/// origin's HEAD, falling back to the local HEAD if there's no origin
+/// (or it doesn't have a HEAD).
+fn default_branch(path: &Path) -> String {
+    run_git(
+        path,
+        &["symbolic-ref", "--quiet", "refs/remotes/origin/HEAD"],
+    )
+    .map(|branch| branch.trim().to_string())
+    .ok()
+    .filter(|branch| !branch.is_empty())
+    .unwrap_or_else(|| String::from("HEAD"))
+}
+
+/// Converts the dates to UTC, so that the commits are counted on the
+/// day they were made on in UTC instead of their own timezone.
+pub fn convert_to_utc(commit_dates: &mut [(DateTime<FixedOffset>, Commit)]) {
+    let utc = FixedOffset::east(0);
+    for (date, _) in commit_dates {
+        *date = date.with_timezone(&utc);
+    }
+}
+
+/// Removes the commits dated after the current time, so that a
+/// single skewed timestamp doesn't add empty years to the graph.
+pub fn drop_future_dates(commit_dates: &mut Vec<(DateTime<FixedOffset>, Commit)>) {
+    let now = Utc::now();
+    let count_before = commit_dates.len();
+    commit_dates.retain(|(date, _)| date.with_timezone(&Utc) <= now);
+    let dropped = count_before - commit_dates.len();
+    if dropped > 0 {
+        log::verbose_println(
+            &format!("dropped {} commits dated in the future", dropped),
+            false,
+        );
+    }
+}
+
+/// Removes the commits dated on the current day, which is still
+/// incomplete. The day is in the commit's timezone, like the days of
+/// the grid.
+pub fn drop_todays_dates(commit_dates: &mut Vec<(DateTime<FixedOffset>, Commit)>) {
+    let now = Utc::now();
+    let count_before = commit_dates.len();
+    commit_dates.retain(|(date, _)| {
+        let today = now.with_timezone(date.offset()).naive_local().date();
+        date.naive_local().date() != today
+    });
+    let dropped = count_before - commit_dates.len();
+    if dropped > 0 {
+        log::verbose_println(&format!("dropped {} commits dated today", dropped), false);
+    }
+}
+
+/// Updates `first_commits` with the date of each project's earliest
+/// commit in `commit_dates`, so that it can be kept up to date over
+/// several batches of commits.
+pub fn track_first_commits(
+    first_commits: &mut HashMap<ProjectMetadata, NaiveDate>,
+    commit_dates: &[(DateTime<FixedOffset>, Commit)],
+) {
+    for (date, commit) in commit_dates {
+        let date = date.naive_local().date();
+        let first = (first_commits.entry(commit.project.clone())).or_insert(date);
+        *first = (*first).min(date);
+    }
+}
+
+/// Removes the commits made before the `last_years` most recent
+/// years with commits.
+pub fn drop_older_years(
+    commit_dates: &mut Vec<(DateTime<FixedOffset>, Commit)>,
+    last_years: usize,
+) {
+    let Some(last_year) = commit_dates.iter().map(|(date, _)| date.year()).max() else {
+        return;
+    };
+    let first_year = last_year - last_years as i32 + 1;
+    commit_dates.retain(|(date, _)| date.year() >= first_year);
+}
+
+/// Removes the repositories whose latest commit is older than
+/// `days` days, so that the stale ones don't need to be scanned. The
+/// repositories whose latest commit can't be read are kept, as
+/// they're not known to be inactive.
+pub fn drop_inactive_repos(repos: &mut Vec<ProjectMetadata>, days: u64) {
+    let window = i64::try_from(days)
+        .unwrap_or(i64::MAX)
+        .saturating_mul(24 * 60 * 60);
+    let cutoff = Utc::now().timestamp().saturating_sub(window);
+    let is_active = |repo: &ProjectMetadata| -> bool {
+        let latest = run_git(&repo.path, &["log", "-1", "--all", "--format=%at"]);
+        match latest.as_deref().map(str::trim) {
+            // A repository without any commits prints nothing
+            Ok("") => false,
+            Ok(latest) => {
+                if let Ok(time) = latest.parse::<i64>() {
+                    time >= cutoff
+                } else {
+                    log::error_println(&format!(
+                        "error: could not parse the latest commit's time in {}: {:?}",
+                        repo.path.display(),
+                        latest
+                    ));
+                    true
+                }
+            }
+            Err(err) => {
+                log::error_println(&format!(
+                    "error: could not read the latest commit of {}, keeping it: {}",
+                    repo.path.display(),
+                    err
+                ));
+                true
+            }
+        }
+    };
+
+    #[cfg(feature = "rayon")]
+    let active: Vec<bool> = repos.par_iter().map(is_active).collect();
+    #[cfg(not(feature = "rayon"))]
+    let active: Vec<bool> = repos.iter().map(is_active).collect();
+
+    let count_before = repos.len();
+    let mut active = active.into_iter();
+    repos.retain(|_| active.next().unwrap_or(false));
+    let dropped = count_before - repos.len();
+    if dropped > 0 {
+        log::println(&format!(
+            "excluded {} repositories without commits in the last {} days",
+            dropped, days
+        ));
+    }
+}
+
+/// Runs git with the arguments in the directory, and returns its
+/// stdout, or an error describing why git failed.
+pub fn run_git(work_dir: &Path, args: &[&str]) -> Result<String, String> {
     let output = Command::new("git")
         .args(args)
         .current_dir(work_dir)
         .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .output()
-        .unwrap();
-    String::from_utf8_lossy(&output.stdout).to_string()
+        .map_err(|err| format!("could not run git ({})", err))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format!(
+            "git exited with {} ({})",
+            output.status,
+            stderr.trim()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use std::env;
+    use std::fs;
+
+    use super::{count_lines_changed, has_matching_coauthor, read_log, run_git, LogRecord};
+    use crate::render::COMMIT_SIZE_BUCKETS;
+    use crate::GenerationData;
+
+    /// `read_log`'s output with both --commit-size-buckets and
+    /// --count-coauthors, for a commit that renames a file and adds a
+    /// binary one, and a commit that adds a file.
+    static LOG: &str = "\x1e2021-03-02 12:00:00 +0200\tA\ta@example.com\tsecond\tCo-authored-by: B <b@example.com>\n\x1f\n1\t0\ta.txt => b.txt\n-\t-\tbin\n\n\x1e2021-03-01 12:00:00 +0200\tA\ta@example.com\tfirst\t\x1f\n3\t0\ta.txt\n";
+
+    #[test]
+    fn log_records_are_parsed_with_their_numstat() {
+        let records: Vec<LogRecord> = LOG.split('\x1e').filter_map(LogRecord::parse).collect();
+        assert_eq!(records.len(), 2);
+        let (second, first) = (&records[0], &records[1]);
+        assert_eq!((second.author, second.subject), ("A", "second"));
+        let coauthor = Regex::new("^B <b@example.com>$").unwrap();
+        assert!(has_matching_coauthor(second.body, &coauthor));
+        assert!(!has_matching_coauthor(first.body, &coauthor));
+        // The binary file's "-" counts are skipped
+        assert_eq!(count_lines_changed(second.numstat), 1);
+        assert_eq!(count_lines_changed(first.numstat), 3);
+
+        // Without --count-coauthors, there's no body before the \x1f
+        let record = LogRecord::parse("2021-03-01 12:00:00 +0200\tA\ta@example.com\tfirst\x1f\n12\t3\tsrc/{old.rs => new.rs}\n").unwrap();
+        assert_eq!((record.subject, record.body), ("first", ""));
+        assert_eq!(count_lines_changed(record.numstat), 15);
+    }
+
+    #[test]
+    fn renames_are_counted_as_small_commits() {
+        let root = env::temp_dir().join(format!("activity-graph-rename-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let git = |args: &[&str]| {
+            let config = ["-c", "user.name=A", "-c", "user.email=a@example.com"];
+            run_git(&root, &[&config[..], args].concat()).unwrap();
+        };
+        git(&["init", "-q"]);
+        git(&["config", "diff.renames", "false"]);
+        fs::write(root.join("a.txt"), "line\n".repeat(20)).unwrap();
+        git(&["add", "a.txt"]);
+        git(&["commit", "-q", "-m", "add"]);
+        git(&["mv", "a.txt", "b.txt"]);
+        git(&["commit", "-q", "-m", "rename"]);
+
+        let gen = GenerationData {
+            commit_size_buckets: true,
+            ..GenerationData::default()
+        };
+        let log = read_log(&gen, &root);
+        let _ = fs::remove_dir_all(&root);
+        let rename = (log.split('\x1e'))
+            .filter_map(LogRecord::parse)
+            .find(|record| record.subject == "rename")
+            .unwrap();
+        let (_, tiny_limit) = COMMIT_SIZE_BUCKETS[0];
+        assert!(count_lines_changed(rename.numstat) < tiny_limit.unwrap());
+    }
 }
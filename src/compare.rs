@@ -0,0 +1,154 @@
+//! Contains the comparison page of the `compare` subcommand: two
+//! years' grids side by side, with the changes between them.
+use chrono::NaiveDate;
+
+use crate::render::{self, cell_date, get_label, get_total_count, SvgOptions};
+use crate::{ExternalResources, Year};
+
+static COMPARE_CSS: &str = "
+.compare {
+    text-align: center;
+    margin-top: 3em;
+}
+
+.compare-deltas {
+    display: flex;
+    justify-content: space-around;
+    margin: 2em 0;
+}
+
+.compare-deltas strong {
+    display: block;
+    font-size: 1.75rem;
+}
+
+.compare-grids {
+    display: flex;
+    flex-wrap: wrap;
+    justify-content: center;
+    gap: 2em;
+}
+";
+
+/// The numbers compared between the years.
+#[derive(Debug, PartialEq, Eq)]
+struct Stats {
+    commits: usize,
+    active_days: usize,
+    longest_streak: usize,
+}
+
+impl Stats {
+    fn for_year(year: &Year) -> Stats {
+        let mut active_dates: Vec<NaiveDate> = (0..year.days.len())
+            .filter(|&i| !year.days[i].filler && !year.days[i].commits.is_empty())
+            .map(|i| cell_date(year, i))
+            .collect();
+        active_dates.sort_unstable();
+        Stats {
+            commits: get_total_count(year),
+            active_days: active_dates.len(),
+            longest_streak: longest_streak(&active_dates),
+        }
+    }
+}
+
+/// Renders the comparison of `year` against the `previous` one, with
+/// the changes in the commits, active days and longest streak.
+pub fn render(ext: &ExternalResources, year: &Year, previous: &Year) -> String {
+    let (stats, previous_stats) = (Stats::for_year(year), Stats::for_year(previous));
+    let deltas = [
+        ("commits", stats.commits, previous_stats.commits),
+        ("active days", stats.active_days, previous_stats.active_days),
+        (
+            "days in the longest streak",
+            stats.longest_streak,
+            previous_stats.longest_streak,
+        ),
+    ];
+
+    let mut result = String::with_capacity(1024);
+    let title = format!("{} compared to {}", get_label(year), get_label(previous));
+    result += &format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width,initial-scale=1\">\n<title>{0}</title>\n<style>\n{1}{2}</style>\n</head>\n<body>\n<div class=\"compare\">\n<h1>{0}</h1>\n",
+        title,
+        render::css(ext, &[year.clone(), previous.clone()]),
+        COMPARE_CSS
+    );
+    result += "<div class=\"compare-deltas\">\n";
+    for (label, value, previous_value) in &deltas {
+        result += &format!(
+            " <div><strong>{}</strong>{} {}, {} in {}</div>\n",
+            format_delta(*value, *previous_value),
+            value,
+            label,
+            previous_value,
+            get_label(previous)
+        );
+    }
+    result += "</div>\n<div class=\"compare-grids\">\n";
+    for grid in &[previous, year] {
+        result += &render::svg(
+            std::slice::from_ref(*grid),
+            None,
+            None,
+            &SvgOptions::EMBEDDED,
+        );
+    }
+    result += "</div>\n</div>\n</body></html>\n";
+    result
+}
+
+/// Returns the length of the longest run of consecutive dates.
+fn longest_streak(sorted_dates: &[NaiveDate]) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for (i, date) in sorted_dates.iter().enumerate() {
+        let continues = i > 0 && sorted_dates[i - 1].succ() == *date;
+        current = if continues { current + 1 } else { 1 };
+        longest = longest.max(current);
+    }
+    longest
+}
+
+/// Formats the change from `previous` to `value`, e.g. "+12 (+50%)".
+fn format_delta(value: usize, previous: usize) -> String {
+    let delta = value as i64 - previous as i64;
+    let sign = if delta > 0 { "+" } else { "" };
+    if previous == 0 {
+        format!("{}{}", sign, delta)
+    } else {
+        let percent = delta as f64 / previous as f64 * 100.0;
+        format!("{}{} ({}{:.0}%)", sign, delta, sign, percent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_delta, Stats};
+    use crate::render::{self, tests::commits_on};
+
+    #[test]
+    fn stats_count_the_longest_streak() {
+        let commits = commits_on(&[
+            (2021, 3, 1),
+            (2021, 3, 1),
+            (2021, 3, 2),
+            (2021, 3, 4),
+            (2021, 3, 5),
+            (2021, 3, 6),
+        ]);
+        let year = render::gather_years(commits, true).remove(0);
+        assert_eq!(
+            Stats::for_year(&year),
+            Stats {
+                commits: 6,
+                active_days: 5,
+                longest_streak: 3,
+            }
+        );
+        assert_eq!(format_delta(15, 10), "+5 (+50%)");
+        assert_eq!(format_delta(5, 10), "-5 (-50%)");
+        assert_eq!(format_delta(3, 0), "+3");
+    }
+}
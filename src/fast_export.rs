@@ -0,0 +1,195 @@
+//! Contains the parsing of `git fast-export` streams, for graphing
+//! repositories that can't be accessed directly.
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::commits::{self, AuthorMap, GitDateMode, LogicalCommits};
+use crate::{log, Commit, GenerationData, ProjectMetadata};
+
+/// Reads the commits from the fast-export streams in
+/// `gen.fast_export`, applying the same author and message filters as
+/// the commits read from repositories. Each stream is counted as its
+/// own project, named after the file.
+pub fn find_dates(
+    gen: &GenerationData,
+    author_map: &AuthorMap,
+) -> Vec<(DateTime<FixedOffset>, Commit)> {
+    let author_regex = match commits::author_regex(gen) {
+        Ok(regex) => regex,
+        Err(err) => {
+            log::error_println(&format!("error: invalid author regex: {}", err));
+            return Vec::new();
+        }
+    };
+
+    let mut commit_dates = Vec::new();
+    for path in &gen.fast_export {
+        let stream = if path == Path::new("-") {
+            let mut bytes = Vec::new();
+            io::stdin().read_to_end(&mut bytes).map(|_| bytes)
+        } else {
+            fs::read(path)
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::error_println(&format!(
+                    "error: could not read the fast-export stream {}: {}",
+                    path.display(),
+                    err
+                ));
+                continue;
+            }
+        };
+
+        let name = path
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .filter(|name| *name != "-")
+            .unwrap_or("stdin");
+        let project = ProjectMetadata {
+            name: name.to_string(),
+            path: path.canonicalize().unwrap_or_else(|_| PathBuf::from(path)),
+        };
+        let count_before = commit_dates.len();
+        let (commits, unparsed) = parse(&stream);
+        let mut logical_commits = LogicalCommits::default();
+        if gen.strict && unparsed > 0 {
+            log::error_println(&format!(
+                "error: could not parse the author or committer date of {} commits in the fast-export stream {}",
+                unparsed,
+                path.display()
+            ));
+        }
+        for commit in commits {
+            let author_matches = author_regex.as_ref().is_none_or(|regex| {
+                regex.is_match(&commit.author)
+                    || gen.count_coauthors && commits::has_matching_coauthor(&commit.message, regex)
+            });
+            let subject = commit.message.lines().next().unwrap_or("");
+            let excluded = gen.exclude_message.iter().any(|re| re.is_match(subject));
+            let copy = gen.stable_dates
+                && author_matches
+                && !excluded
+                && !logical_commits.insert(&commit.author, subject, &commit.date);
+            if author_matches && !excluded && !copy {
+                // The identity is "Name <email>", only the name is kept
+                let (name, email) = commit
+                    .author
+                    .split_once(" <")
+                    .unwrap_or((&commit.author, ""));
+                let counted = Commit {
+                    project: project.clone(),
+                    author: author_map
+                        .canonical(name, email.trim_end_matches('>'))
+                        .to_string(),
+                    lines_changed: None,
+                };
+                let date = match gen.git_date_mode {
+                    // The same dates that git would give out
+                    GitDateMode::Local => DateTime::from(commit.date.with_timezone(&Local)),
+                    GitDateMode::Iso => commit.date,
+                };
+                commit_dates.push((date, counted));
+            }
+        }
+        log::verbose_println(
+            &format!(
+                "read {} commits from the fast-export stream {}",
+                commit_dates.len() - count_before,
+                path.display()
+            ),
+            false,
+        );
+    }
+    commit_dates
+}
+
+struct ExportedCommit {
+    /// The author's identity, in the same "Name <email>" form that
+    /// `git log --author` matches against.
+    author: String,
+    date: DateTime<FixedOffset>,
+    message: String,
+}
+
+/// Parses the commits out of the stream, and counts the ones without a
+/// parseable author or committer line. The data blocks are skipped by
+/// their length, so file contents can't be mistaken for commands.
+fn parse(stream: &[u8]) -> (Vec<ExportedCommit>, usize) {
+    let mut commits = Vec::new();
+    let mut unparsed = 0;
+    let mut in_commit = false;
+    let mut author = None;
+    let mut committer = None;
+
+    let mut pos = 0;
+    while pos < stream.len() {
+        let line_end = stream[pos..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(stream.len(), |i| pos + i);
+        let line = String::from_utf8_lossy(&stream[pos..line_end]);
+        pos = line_end + 1;
+
+        if let Some(length) = line.strip_prefix("data ") {
+            let length = length.parse::<usize>().unwrap_or(0);
+            let data_end = (pos + length).min(stream.len());
+            let data = &stream[pos.min(data_end)..data_end];
+            pos = data_end;
+            if in_commit {
+                // The commit's message is the only data block in it
+                if let Some((author, date)) = author.take().or_else(|| committer.take()) {
+                    commits.push(ExportedCommit {
+                        author,
+                        date,
+                        message: String::from_utf8_lossy(data).to_string(),
+                    });
+                } else {
+                    unparsed += 1;
+                }
+                in_commit = false;
+            }
+        } else if line.starts_with("commit ") {
+            in_commit = true;
+            author = None;
+            committer = None;
+        } else if in_commit {
+            if let Some(ident) = line.strip_prefix("author ") {
+                author = parse_ident(ident);
+            } else if let Some(ident) = line.strip_prefix("committer ") {
+                committer = parse_ident(ident);
+            }
+        }
+    }
+    (commits, unparsed)
+}
+
+/// Parses "Name <email> 1234567890 +0000" into the identity and the
+/// date.
+fn parse_ident(ident: &str) -> Option<(String, DateTime<FixedOffset>)> {
+    let mut parts = ident.rsplitn(3, ' ');
+    let offset = parse_offset(parts.next()?)?;
+    let timestamp = parts.next()?.parse().ok()?;
+    let name = parts.next()?;
+    let date = offset.timestamp_opt(timestamp, 0).single()?;
+    Some((name.to_string(), date))
+}
+
+/// Parses a timezone offset like "+0300" or "-0130".
+fn parse_offset(timezone: &str) -> Option<FixedOffset> {
+    let (sign, digits) = match timezone.split_at(1) {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
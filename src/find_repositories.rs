@@ -1,65 +1,341 @@
-use std::collections::HashSet;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::ffi::OsStr;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::{commits, log, ProjectMetadata};
+
+/// Determines what the names of the found projects are based on.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum NameSource {
+    /// The name of the repository's directory.
+    #[default]
+    Dir,
+    /// The repository's path relative to the input directory.
+    Path,
+    /// The url of the repository's origin remote.
+    Remote,
+}
+
+impl FromStr for NameSource {
+    type Err = String;
+    fn from_str(s: &str) -> Result<NameSource, String> {
+        match s {
+            "dir" => Ok(NameSource::Dir),
+            "path" => Ok(NameSource::Path),
+            "remote" => Ok(NameSource::Remote),
+            _ => Err(format!("unknown name source: {}", s)),
+        }
+    }
+}
+
+/// Controls which directories are descended into while scanning.
+pub struct ScanOptions {
+    pub follow_symlinks: bool,
+    pub scan_hidden_dirs: bool,
+    /// The time after which the scan is stopped, keeping the
+    /// repositories found so far.
+    pub timeout: Option<Duration>,
+    /// Report the directories that can't be read as errors, instead
+    /// of skipping them quietly.
+    pub strict: bool,
+    /// The depth below the input directories from which on the
+    /// subdirectories are scanned in parallel, or None to scan
+    /// everything serially.
+    pub parallel_below_depth: Option<usize>,
+}
+
+/// Expands the glob patterns in the input paths into the directories
+/// they match. A leading `~` is replaced with the home directory,
+/// `{a,b}` expands into both alternatives, and `*` and `?` match any
+/// characters within a path component. The paths without patterns are
+/// returned as they are, and a warning is printed for the patterns
+/// that don't match anything.
+pub fn expand_globs(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut expanded = Vec::with_capacity(paths.len());
+    for path in paths {
+        let Some(pattern) = path.to_str() else {
+            expanded.push(path.clone());
+            continue;
+        };
+        let pattern = match (pattern.strip_prefix('~'), env::var_os("HOME")) {
+            (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+                format!("{}{}", home.to_string_lossy(), rest)
+            }
+            _ => pattern.to_string(),
+        };
+        if !pattern.contains(['*', '?', '{']) {
+            expanded.push(PathBuf::from(pattern));
+            continue;
+        }
+        let mut matches: Vec<PathBuf> = expand_braces(&pattern)
+            .iter()
+            .flat_map(|pattern| match_components(Path::new(pattern)))
+            .collect();
+        if matches.is_empty() {
+            log::println(&format!(
+                "warning: the input pattern {} didn't match any directories",
+                path.display()
+            ));
+        }
+        matches.sort();
+        matches.dedup();
+        expanded.extend(matches);
+    }
+    expanded
+}
 
-use crate::{log, ProjectMetadata};
+/// Expands the first `{a,b}` group of the pattern into each of its
+/// alternatives, recursively for the rest of the groups.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some(start) = pattern.find('{') else {
+        return vec![pattern.to_string()];
+    };
+    let mut depth = 0;
+    let mut alternatives = Vec::new();
+    let mut alternative_start = start + 1;
+    for (i, c) in pattern.char_indices().skip_while(|&(i, _)| i <= start) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(&pattern[alternative_start..i]);
+                alternative_start = i + 1;
+            }
+            '}' => {
+                alternatives.push(&pattern[alternative_start..i]);
+                let (prefix, suffix) = (&pattern[..start], &pattern[i + 1..]);
+                return alternatives
+                    .iter()
+                    .flat_map(|alternative| {
+                        expand_braces(&format!("{}{}{}", prefix, alternative, suffix))
+                    })
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+    // An unclosed brace is matched as it is
+    vec![pattern.to_string()]
+}
 
-pub fn from_paths(paths: &[PathBuf], depth: Option<i32>) -> HashSet<ProjectMetadata> {
+/// Returns the directories matching the pattern, one component at a
+/// time.
+fn match_components(pattern: &Path) -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let wildcard = match component {
+            Component::Normal(name) => name.to_str().filter(|name| name.contains(['*', '?'])),
+            _ => None,
+        };
+        let Some(wildcard) = wildcard else {
+            for candidate in &mut candidates {
+                candidate.push(component);
+            }
+            continue;
+        };
+        let wildcard: Vec<char> = wildcard.chars().collect();
+        let mut matches = Vec::new();
+        for candidate in &candidates {
+            let dir = if candidate.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                candidate
+            };
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let name = entry.file_name();
+                let Some(name) = name.to_str() else {
+                    continue;
+                };
+                let name: Vec<char> = name.chars().collect();
+                // Like in shells, the hidden entries are only matched
+                // by patterns starting with a dot
+                let hidden = name.first() == Some(&'.') && wildcard.first() != Some(&'.');
+                if !hidden && wildcard_matches(&wildcard, &name) {
+                    matches.push(candidate.join(entry.file_name()));
+                }
+            }
+        }
+        candidates = matches;
+    }
+    candidates.retain(|candidate| candidate.is_dir());
+    candidates
+}
+
+/// Returns true if the name matches the wildcard pattern, where `*`
+/// matches any amount of characters and `?` matches any one
+/// character.
+fn wildcard_matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| wildcard_matches(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && wildcard_matches(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && wildcard_matches(rest, &name[1..]),
+    }
+}
+
+pub fn from_paths(
+    paths: &[PathBuf],
+    depth: Option<i32>,
+    name_source: NameSource,
+    options: &ScanOptions,
+) -> HashSet<ProjectMetadata> {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    #[cfg(not(feature = "rayon"))]
+    if options.parallel_below_depth.is_some() {
+        log::println(
+            "warning: --parallel-below-depth requires the rayon feature, scanning serially",
+        );
+    }
     let repos = paths
         .iter()
         .map(|repo_dir| {
-            match fs::read_dir(&repo_dir) {
+            match fs::read_dir(repo_dir) {
                 Ok(subdirs) => {
                     // Find all the repository directories
                     let mut repos = HashSet::new();
-                    analyze_dir(&mut repos, &repo_dir, subdirs, depth);
-                    repos
+                    analyze_dir(&mut repos, repo_dir, subdirs, depth, 0, options, deadline);
+                    if name_source == NameSource::Dir {
+                        repos
+                    } else {
+                        repos
+                            .into_iter()
+                            .map(|repo| rename(repo, repo_dir, name_source))
+                            .collect()
+                    }
                 }
                 Err(err) => {
-                    eprintln!("error: cannot read directory ({})", err);
+                    log::error_println(&format!(
+                        "error: cannot read directory {}: {}",
+                        repo_dir.display(),
+                        err
+                    ));
                     HashSet::new()
                 }
             }
         })
-        .fold(HashSet::new(), |mut a, b| {
-            a.extend(b);
+        // Overlapping inputs can find the same repository under different
+        // names, so the first input's name wins
+        .fold(HashMap::new(), |mut a, b| {
+            for repo in b {
+                a.entry(repo.path.clone()).or_insert(repo);
+            }
             a
         });
+    let repos: HashSet<ProjectMetadata> = repos.into_values().collect();
+    if let (Some(timeout), Some(deadline)) = (options.timeout, deadline) {
+        if Instant::now() >= deadline {
+            log::println(&format!(
+                "warning: scanning timed out after {:?}, continuing with the {} repositories found so far",
+                timeout,
+                repos.len()
+            ));
+        }
+    }
     log::verbose_println("finished scanning for git repositories", false);
     repos
 }
 
+fn rename(repo: ProjectMetadata, root: &Path, name_source: NameSource) -> ProjectMetadata {
+    let name = match name_source {
+        NameSource::Dir => None,
+        NameSource::Path => repo
+            .path
+            .strip_prefix(root.canonicalize().unwrap_or_else(|_| root.to_path_buf()))
+            .ok()
+            .and_then(Path::to_str)
+            .filter(|path| !path.is_empty())
+            .map(ToString::to_string),
+        NameSource::Remote => {
+            // Repositories without an origin just keep their directory name
+            commits::run_git(&repo.path, &["remote", "get-url", "origin"])
+                .ok()
+                .and_then(|url| name_from_remote_url(url.trim()))
+        }
+    };
+    match name {
+        Some(name) => ProjectMetadata { name, ..repo },
+        None => repo,
+    }
+}
+
+/// Parses the "owner/repository" part out of a remote url, e.g.
+/// `git@example.com:owner/repository.git` or
+/// `https://example.com/owner/repository`.
+fn name_from_remote_url(url: &str) -> Option<String> {
+    let url = url.trim_end_matches('/');
+    let url = url.trim_end_matches(".git");
+    let mut parts = url.rsplit(&['/', ':'][..]).filter(|s| !s.is_empty());
+    let repository = parts.next()?;
+    match parts.next() {
+        Some(owner) => Some(format!("{}/{}", owner, repository)),
+        None => Some(repository.to_string()),
+    }
+}
+
 fn analyze_dir(
     git_paths: &mut HashSet<ProjectMetadata>,
     path: &Path,
     dirs: fs::ReadDir,
     depth: Option<i32>,
+    level: usize,
+    options: &ScanOptions,
+    deadline: Option<Instant>,
 ) {
-    if let Some(path) = path
-        .canonicalize()
-        .ok()
-        .map(PathBuf::into_os_string)
-        .and_then(|path| path.into_string().ok())
-    {
-        log::verbose_println(&format!("scanning: {}\r", path), true);
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return;
     }
 
-    let dirs: Vec<fs::DirEntry> = dirs.filter_map(Result::ok).collect();
+    // Symlinks can lead to the same repository through different
+    // paths, so the canonical path is used to tell them apart
+    let canonical_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    log::verbose_println(&format!("scanning: {}\r", canonical_path.display()), true);
+
+    let dirs: Vec<fs::DirEntry> = dirs
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                if options.strict {
+                    log::error_println(&format!(
+                        "error: cannot read an entry of {}: {}",
+                        path.display(),
+                        err
+                    ));
+                }
+                None
+            }
+        })
+        .collect();
     if dirs
         .iter()
         .map(fs::DirEntry::file_name)
         .any(|file_name| file_name == ".git")
     {
-        if let Some(name) = path
+        if let Some(name) = canonical_path
             .file_name()
             .and_then(OsStr::to_str)
             .map(ToString::to_string)
         {
-            git_paths.insert(ProjectMetadata {
+            let repo = ProjectMetadata {
                 name,
-                path: PathBuf::from(&path),
-            });
+                path: canonical_path.clone(),
+            };
+            if !git_paths.insert(repo) {
+                log::println(&format!(
+                    "warning: found {} again through {}, it will only be counted once",
+                    canonical_path.display(),
+                    path.display()
+                ));
+            }
         }
     }
 
@@ -68,27 +344,191 @@ fn analyze_dir(
         return;
     }
 
-    for dir in dirs {
-        let path = dir.path();
-        if path.file_name().iter().any(|name| *name != ".git") {
-            let fix_symlink = |link_path: PathBuf| {
-                // Fill out the path if it's relative, because it's
-                // relative to the path variable (at least on windows,
-                // should probably test this on other OSes as well,
-                // but, well, I rarely use symlinks).
-                if let Some(base) = path.parent() {
-                    if !link_path.is_absolute() {
-                        let mut fixed_symlink = PathBuf::from(base);
-                        fixed_symlink.push(link_path);
-                        return fixed_symlink;
-                    }
-                }
-                link_path
-            };
-            let path = fs::read_link(&path).map(fix_symlink).unwrap_or(path);
-            if let Ok(dirs) = fs::read_dir(&path) {
-                analyze_dir(git_paths, &path, dirs, depth.map(|depth| depth - 1));
+    let subdirs: Vec<PathBuf> = (dirs.iter())
+        .filter_map(|dir| subdir_to_scan(dir, options))
+        .collect();
+    let depth = depth.map(|depth| depth - 1);
+    #[cfg(feature = "rayon")]
+    if (options.parallel_below_depth).is_some_and(|parallel_depth| level >= parallel_depth) {
+        let found: Vec<HashSet<ProjectMetadata>> = subdirs
+            .par_iter()
+            .map(|subdir| {
+                let mut found = HashSet::new();
+                scan_subdir(&mut found, subdir, depth, level + 1, options, deadline);
+                found
+            })
+            .collect();
+        for repo in found.into_iter().flatten() {
+            let repo_path = repo.path.clone();
+            if !git_paths.insert(repo) {
+                log::println(&format!(
+                    "warning: found {} more than once, it will only be counted once",
+                    repo_path.display()
+                ));
             }
         }
+        return;
+    }
+    for subdir in &subdirs {
+        scan_subdir(git_paths, subdir, depth, level + 1, options, deadline);
+    }
+}
+
+/// Returns the path of the directory entry if it should be scanned,
+/// with symlinks resolved.
+fn subdir_to_scan(dir: &fs::DirEntry, options: &ScanOptions) -> Option<PathBuf> {
+    let path = dir.path();
+    if path.file_name().is_none_or(|name| name == ".git") {
+        return None;
+    }
+    let is_hidden = dir.file_name().to_string_lossy().starts_with('.');
+    if is_hidden && !options.scan_hidden_dirs {
+        return None;
+    }
+    let is_symlink =
+        fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink());
+    if is_symlink && !options.follow_symlinks {
+        return None;
+    }
+    let fix_symlink = |link_path: PathBuf| {
+        // Fill out the path if it's relative, because it's
+        // relative to the path variable (at least on windows,
+        // should probably test this on other OSes as well,
+        // but, well, I rarely use symlinks).
+        if let Some(base) = path.parent() {
+            if !link_path.is_absolute() {
+                let mut fixed_symlink = PathBuf::from(base);
+                fixed_symlink.push(link_path);
+                return fixed_symlink;
+            }
+        }
+        link_path
+    };
+    Some(fs::read_link(&path).map(fix_symlink).unwrap_or(path))
+}
+
+/// Scans the subdirectory for repositories, if it's a readable
+/// directory.
+fn scan_subdir(
+    git_paths: &mut HashSet<ProjectMetadata>,
+    path: &Path,
+    depth: Option<i32>,
+    level: usize,
+    options: &ScanOptions,
+    deadline: Option<Instant>,
+) {
+    match fs::read_dir(path) {
+        Ok(dirs) => analyze_dir(git_paths, path, dirs, depth, level, options, deadline),
+        Err(err) if options.strict && path.is_dir() => {
+            log::error_println(&format!(
+                "error: cannot read directory {}: {}",
+                path.display(),
+                err
+            ));
+        }
+        // Files can't be read as directories, which is fine
+        Err(_) => {}
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::slice;
+
+    use super::{expand_braces, expand_globs, from_paths, NameSource, ScanOptions};
+
+    fn options(scan_hidden_dirs: bool) -> ScanOptions {
+        ScanOptions {
+            follow_symlinks: true,
+            scan_hidden_dirs,
+            timeout: None,
+            strict: false,
+            parallel_below_depth: None,
+        }
+    }
+
+    #[test]
+    fn symlinked_repository_is_found_once() {
+        let root = env::temp_dir().join(format!("activity-graph-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("repo").join(".git")).unwrap();
+        symlink(root.join("repo"), root.join("link")).unwrap();
+
+        let repos = from_paths(
+            slice::from_ref(&root),
+            None,
+            NameSource::Dir,
+            &options(false),
+        );
+        let parallel = ScanOptions {
+            parallel_below_depth: Some(0),
+            ..options(false)
+        };
+        let parallel_repos = from_paths(slice::from_ref(&root), None, NameSource::Dir, &parallel);
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(repos.len(), 1);
+        assert!(parallel_repos == repos);
+    }
+
+    #[test]
+    fn overlapping_inputs_name_a_repository_once() {
+        let root = env::temp_dir().join(format!("activity-graph-overlap-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("work").join("repo").join(".git")).unwrap();
+
+        let repos = from_paths(
+            &[root.clone(), root.join("work")],
+            None,
+            NameSource::Path,
+            &options(false),
+        );
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(repos.len(), 1);
+        assert!(repos.iter().all(|repo| repo.name == "work/repo"));
+    }
+
+    #[test]
+    fn globs_are_expanded_into_the_matching_directories() {
+        let root = env::temp_dir().join(format!("activity-graph-glob-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in &["a/repos", "b/repos", "c/other", ".d/repos"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let expanded = expand_globs(&[root.join("*/repos"), root.join("{c,e}/*")]);
+        let _ = fs::remove_dir_all(&root);
+        let expected = vec![
+            root.join("a/repos"),
+            root.join("b/repos"),
+            root.join("c/other"),
+        ];
+        assert_eq!(expanded, expected);
+        assert_eq!(expand_braces("x{a,b{c,d}}y"), vec!["xay", "xbcy", "xbdy"]);
+    }
+
+    #[test]
+    fn hidden_directories_are_only_scanned_when_asked() {
+        let root = env::temp_dir().join(format!("activity-graph-hidden-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(".hidden").join("repo").join(".git")).unwrap();
+
+        let skipped = from_paths(
+            slice::from_ref(&root),
+            None,
+            NameSource::Dir,
+            &options(false),
+        );
+        let scanned = from_paths(
+            slice::from_ref(&root),
+            None,
+            NameSource::Dir,
+            &options(true),
+        );
+        let _ = fs::remove_dir_all(&root);
+        assert!(skipped.is_empty());
+        assert_eq!(scanned.len(), 1);
     }
 }
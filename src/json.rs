@@ -0,0 +1,299 @@
+//! Contains the json rendering of the commit counts, for other
+//! programs to consume.
+use chrono::{FixedOffset, NaiveDate, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashSet};
+use std::iter;
+use std::path::PathBuf;
+
+use crate::render::{cell_date, commit_sizes, gather_years, get_label, get_total_count};
+use crate::{log, render, Commit, Day, ProjectMetadata, Year};
+
+#[derive(Serialize)]
+struct Document<'a> {
+    years: Vec<JsonYear<'a>>,
+    /// Only included when the commit sizes were read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commit_sizes: Option<CommitSizes>,
+    /// Only included when the errors are collected into the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<&'a [String]>,
+}
+
+#[derive(Serialize)]
+struct JsonYear<'a> {
+    year: usize,
+    commits: usize,
+    /// Only included with `--weekly-streaks`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weekly_streaks: Option<JsonWeeklyStreaks>,
+    /// Only included with `--show-gaps`, when the year had days
+    /// without commits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    longest_gap: Option<JsonGap>,
+    /// The days with commits, in chronological order.
+    days: Vec<JsonDay<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonDay<'a> {
+    date: String,
+    commits: usize,
+    /// The amount of commits per project.
+    projects: BTreeMap<&'a str, usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonWeeklyStreaks {
+    active_weeks: usize,
+    longest_streak: usize,
+}
+
+/// The longest run of days without commits, with the dates of its
+/// first and last days.
+#[derive(Serialize, Deserialize)]
+struct JsonGap {
+    days: usize,
+    start: String,
+    end: String,
+}
+
+/// The amount of commits in each of the size buckets, see
+/// `render::COMMIT_SIZE_BUCKETS`.
+#[derive(Serialize)]
+struct CommitSizes {
+    tiny: usize,
+    small: usize,
+    medium: usize,
+    large: usize,
+}
+
+/// The parts of a `Document` that are read back by `parse`, the rest
+/// is recomputed from the days.
+#[derive(Deserialize)]
+struct InputDocument {
+    years: Vec<InputYear>,
+}
+
+#[derive(Deserialize)]
+struct InputYear {
+    year: usize,
+    #[serde(default)]
+    weekly_streaks: Option<JsonWeeklyStreaks>,
+    #[serde(default)]
+    longest_gap: Option<JsonGap>,
+    days: Vec<InputDay>,
+}
+
+#[derive(Deserialize)]
+struct InputDay {
+    date: String,
+    projects: BTreeMap<String, usize>,
+}
+
+#[derive(Serialize)]
+struct Index<'a> {
+    years: Vec<IndexEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<&'a [String]>,
+}
+
+#[derive(Serialize)]
+struct IndexEntry {
+    year: usize,
+    commits: usize,
+    file: String,
+}
+
+/// Renders the years into a json document. If `errors` is set, it's
+/// included in the document as the "errors" array.
+pub fn render(years: &[Year], errors: Option<&[String]>) -> String {
+    let commit_sizes = commit_sizes(years).map(|[tiny, small, medium, large]| CommitSizes {
+        tiny,
+        small,
+        medium,
+        large,
+    });
+    let years = years.iter().map(json_year).collect();
+    to_json(&Document {
+        years,
+        commit_sizes,
+        errors,
+    })
+}
+
+/// Renders each year into its own json document, named after the
+/// year, and an index document listing the years and their files.
+/// The index is the first element of the returned (file name, json)
+/// pairs.
+pub fn render_split(years: &[Year], errors: Option<&[String]>) -> Vec<(String, String)> {
+    let mut entries = Vec::with_capacity(years.len());
+    let mut files = Vec::with_capacity(years.len() + 1);
+    for year in years {
+        let file = format!("activity-{}.json", get_label(year));
+        entries.push(IndexEntry {
+            year: year.year,
+            commits: get_total_count(year),
+            file: file.clone(),
+        });
+        files.push((file, to_json(&json_year(year))));
+    }
+    let index = Index {
+        years: entries,
+        errors,
+    };
+    files.insert(0, (String::from("index.json"), to_json(&index)));
+    files
+}
+
+/// Renders the days into a flat time series of `[timestamp, commits]`
+/// pairs, with the timestamps in milliseconds at the start of each
+/// day in UTC, for Grafana's json datasources. The filler days are
+/// left out, the days without commits are included as zeroes.
+pub fn render_grafana(years: &[Year]) -> String {
+    let mut points: Vec<(i64, usize)> = years
+        .iter()
+        .flat_map(|year| {
+            (0..year.days.len())
+                .filter(move |&i| !year.days[i].filler)
+                .map(move |i| {
+                    let timestamp = cell_date(year, i).and_hms(0, 0, 0).timestamp_millis();
+                    (timestamp, year.days[i].commits.len())
+                })
+        })
+        .collect();
+    points.sort_unstable();
+    to_json(&points)
+}
+
+/// Renders the commits of the day into a json object, or returns
+/// None if the date isn't in any of the years.
+#[cfg(feature = "server")]
+pub fn render_day(years: &[Year], date: NaiveDate) -> Option<String> {
+    let day = years.iter().find_map(|year| {
+        (0..year.days.len())
+            .find(|&i| !year.days[i].filler && cell_date(year, i) == date)
+            .map(|i| &year.days[i])
+    })?;
+    Some(to_json(&json_day(date, day)))
+}
+
+/// Reads the years back from a json document written by `render`.
+/// Only the commit counts of each project are stored in it, so the
+/// commits don't have authors or sizes.
+pub fn parse(json: &str) -> Result<Vec<Year>, String> {
+    let document: InputDocument = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    let utc = FixedOffset::east(0);
+    let mut seen_dates = HashSet::new();
+    let mut commit_dates = Vec::new();
+    for day in document.years.iter().flat_map(|year| &year.days) {
+        let date = parse_date(&day.date)?;
+        // The days are only listed in their own year, but a
+        // hand-edited document could repeat them
+        if !seen_dates.insert(date) {
+            continue;
+        }
+        let time = utc.from_utc_datetime(&date.and_hms(12, 0, 0));
+        for (name, &commits) in &day.projects {
+            let commit = Commit {
+                project: ProjectMetadata {
+                    name: name.clone(),
+                    path: PathBuf::from(name),
+                },
+                author: String::new(),
+                lines_changed: None,
+            };
+            commit_dates.extend(iter::repeat_n((time, commit), commits));
+        }
+    }
+
+    let mut years = gather_years(commit_dates, true);
+    for year in &mut years {
+        let input_year = document.years.iter().find(|input| input.year == year.year);
+        year.weekly_streaks = input_year
+            .and_then(|input| input.weekly_streaks.as_ref())
+            .map(|streaks| render::WeeklyStreaks {
+                active_weeks: streaks.active_weeks,
+                longest_streak: streaks.longest_streak,
+            });
+        year.longest_gap = match input_year.and_then(|input| input.longest_gap.as_ref()) {
+            Some(gap) => Some(render::LongestGap {
+                days: gap.days,
+                start: parse_date(&gap.start)?,
+                end: parse_date(&gap.end)?,
+            }),
+            None => None,
+        };
+    }
+    Ok(years)
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|err| format!("invalid date {}: {}", date, err))
+}
+
+fn json_day(date: NaiveDate, day: &Day) -> JsonDay<'_> {
+    let mut projects = BTreeMap::new();
+    for commit in &day.commits {
+        *projects.entry(commit.project.name.as_str()).or_insert(0) += 1;
+    }
+    JsonDay {
+        date: date.format("%Y-%m-%d").to_string(),
+        commits: day.commits.len(),
+        projects,
+    }
+}
+
+fn json_year(year: &Year) -> JsonYear<'_> {
+    let mut days = year
+        .days
+        .iter()
+        .enumerate()
+        .filter(|(_, day)| !day.filler && !day.commits.is_empty())
+        .map(|(i, day)| json_day(cell_date(year, i), day))
+        .collect::<Vec<_>>();
+    days.sort_by(|a, b| a.date.cmp(&b.date));
+    JsonYear {
+        year: year.year,
+        commits: get_total_count(year),
+        weekly_streaks: year.weekly_streaks.map(|streaks| JsonWeeklyStreaks {
+            active_weeks: streaks.active_weeks,
+            longest_streak: streaks.longest_streak,
+        }),
+        longest_gap: year.longest_gap.map(|gap| JsonGap {
+            days: gap.days,
+            start: gap.start.format("%Y-%m-%d").to_string(),
+            end: gap.end.format("%Y-%m-%d").to_string(),
+        }),
+        days,
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => json,
+        Err(err) => {
+            log::error_println(&format!("error: could not serialize the json: {}", err));
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::render;
+    use crate::render::tests::{commit, commits_on};
+
+    #[test]
+    fn rendered_json_parses_back_into_the_same_days() {
+        let mut commits = commits_on(&[(2020, 12, 31), (2021, 1, 1), (2021, 1, 1)]);
+        commits[2].1 = commit("b \"quoted\"");
+        let json = super::render(&render::gather_years(commits, true), None);
+        let years = parse(&json).unwrap();
+        assert_eq!(years.len(), 2);
+        assert_eq!(super::render(&years, None), json);
+    }
+}
@@ -1,6 +1,6 @@
 use chrono::{Local, SecondsFormat};
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
@@ -8,15 +8,20 @@ use crate::Verbosity;
 
 lazy_static::lazy_static! {
     static ref LAST_UPDATE_PRINT_TIME: Mutex<Option<Instant>> = Mutex::new(None);
+    static ref COLLECTED_ERRORS: Mutex<Vec<String>> = Mutex::new(Vec::new());
 }
 
 static LAST_PRINT_WAS_UPDATE: AtomicBool = AtomicBool::new(false);
 static VERBOSE: AtomicBool = AtomicBool::new(false);
 static QUIET: AtomicBool = AtomicBool::new(false);
+static ERROR_COUNT: AtomicUsize = AtomicUsize::new(0);
+static COLLECT_ERRORS: AtomicBool = AtomicBool::new(false);
+static PROGRESS_INTERVAL_MS: AtomicU64 = AtomicU64::new(20);
 
 pub fn set_verbosity(verbosity: &Verbosity) {
     VERBOSE.store(verbosity.verbose, Ordering::Relaxed);
     QUIET.store(verbosity.quiet, Ordering::Relaxed);
+    PROGRESS_INTERVAL_MS.store(verbosity.progress_interval, Ordering::Relaxed);
 }
 
 pub fn println(s: &str) {
@@ -25,15 +30,49 @@ pub fn println(s: &str) {
     }
 }
 
+/// Prints out the error like `println`, and counts it towards
+/// `error_count`. If `collect_errors` has been called, the error is
+/// stored for `take_errors` instead of being printed.
+pub fn error_println(s: &str) {
+    ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+    if COLLECT_ERRORS.load(Ordering::Relaxed) {
+        if let Ok(mut errors) = COLLECTED_ERRORS.lock() {
+            errors.push(s.trim_start_matches("error: ").to_string());
+            return;
+        }
+    }
+    println(s);
+}
+
+/// Makes `error_println` collect the errors instead of printing them.
+pub fn collect_errors() {
+    COLLECT_ERRORS.store(true, Ordering::Relaxed);
+}
+
+/// Returns the errors collected so far, and clears them.
+pub fn take_errors() -> Vec<String> {
+    COLLECTED_ERRORS
+        .lock()
+        .map(|mut errors| errors.drain(..).collect())
+        .unwrap_or_default()
+}
+
+/// Returns the amount of errors printed with `error_println` so far.
+pub fn error_count() -> usize {
+    ERROR_COUNT.load(Ordering::Relaxed)
+}
+
 pub fn verbose_println(s: &str, updating_line: bool) {
     if VERBOSE.load(Ordering::Relaxed) {
         let width = term_size::dimensions().map_or(70, |(w, _)| w - 1).max(4);
 
         if updating_line {
-            // Throttle the line updates to once per 20ms, 50 Hz is plenty real-time.
+            // Throttle the line updates to once per --progress-interval,
+            // by default 20ms, 50 Hz is plenty real-time.
             if let Ok(mut last_update) = LAST_UPDATE_PRINT_TIME.lock() {
                 let now = Instant::now();
-                if last_update.is_some() && now - last_update.unwrap() < Duration::from_millis(20) {
+                let interval = Duration::from_millis(PROGRESS_INTERVAL_MS.load(Ordering::Relaxed));
+                if last_update.is_some() && now - last_update.unwrap() < interval {
                     return;
                 }
                 *last_update = Some(now);
@@ -44,7 +83,7 @@ pub fn verbose_println(s: &str, updating_line: bool) {
                 eprint!("{:width$}\r{}...\r", "", &s[..width - 4], width = width);
             } else {
                 eprint!("{:width$}\r{}\r", "", s, width = width);
-            };
+            }
             LAST_PRINT_WAS_UPDATE.store(true, Ordering::Relaxed);
         } else {
             let was_update = LAST_PRINT_WAS_UPDATE.swap(false, Ordering::Relaxed);
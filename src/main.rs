@@ -6,24 +6,47 @@
     clippy::cast_sign_loss,
     clippy::cast_possible_wrap,
     clippy::cast_precision_loss,
-    clippy::must_use_candidate
+    clippy::must_use_candidate,
+    clippy::uninlined_format_args,
+    clippy::format_push_string,
+    clippy::struct_field_names,
+    clippy::non_std_lazy_statics,
+    clippy::doc_markdown,
+    clippy::struct_excessive_bools
 )]
 
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDate};
+use regex::Regex;
 use structopt::StructOpt;
 
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
 #[cfg(feature = "server")]
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
 use std::time;
 
+mod check;
 mod commits;
+mod compare;
+mod fast_export;
 mod find_repositories;
+mod json;
 mod log;
+mod output;
+#[cfg(feature = "pdf")]
+mod pdf;
+mod poster;
 mod render;
 #[cfg(feature = "server")]
 mod server;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod theme;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct ProjectMetadata {
@@ -31,16 +54,79 @@ pub struct ProjectMetadata {
     path: PathBuf,
 }
 
+/// A counted commit, along with the project it's from.
+#[derive(Clone)]
+pub struct Commit {
+    project: ProjectMetadata,
+    author: String,
+    /// The amount of lines added and removed, only read with
+    /// `--commit-size-buckets`.
+    lines_changed: Option<usize>,
+}
+
 #[derive(Clone, Default)]
 pub struct Day {
     filler: bool,
-    commits: Vec<ProjectMetadata>,
+    commits: Vec<Commit>,
+    annotations: Vec<String>,
+    /// The amount of projects whose first commit was on this day.
+    started_projects: usize,
+}
+
+/// A label attached to a specific date, parsed from `DATE=Label`.
+#[derive(Clone)]
+pub struct Annotation {
+    date: NaiveDate,
+    label: String,
+}
+
+impl FromStr for Annotation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Annotation, String> {
+        let mut parts = s.splitn(2, '=');
+        let date = parts.next().unwrap_or("");
+        let label = parts
+            .next()
+            .ok_or_else(|| format!("expected DATE=Label, got: {}", s))?;
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|err| format!("invalid date {}: {}", date, err))?;
+        Ok(Annotation {
+            date,
+            label: label.to_string(),
+        })
+    }
 }
 
 #[derive(Clone)]
 pub struct Year {
     year: usize,
+    /// The last year the grid covers, which differs from `year` only
+    /// for the continuous grid from `--merge-adjacent-years`.
+    end_year: usize,
+    /// The amount of weeks (columns) in the grid.
+    weeks: usize,
+    /// The date of the first cell, the monday of the first week.
+    first_day: NaiveDate,
     days: Vec<Day>,
+    metric: render::Metric,
+    /// The weighting of the commits by their age in the shading, from
+    /// `--recency-halflife`. It's the same for all the years.
+    recency: Option<render::Recency>,
+    /// The commit count at which the days reach the darkest shade, from
+    /// `--clip-at`.
+    clip_at: Option<usize>,
+    /// How much time each cell covers, changed from days when the
+    /// days are aggregated.
+    granularity: render::Granularity,
+    /// The weekly consistency stats, from `--weekly-streaks`. They're
+    /// counted from the days before they're aggregated.
+    weekly_streaks: Option<render::WeeklyStreaks>,
+    /// The longest run of days without commits, from `--show-gaps`.
+    /// None if the flag isn't set, or if there were no such days.
+    longest_gap: Option<render::LongestGap>,
+    /// The repository's name, when graphing a single one with
+    /// `--single`.
+    project: Option<String>,
 }
 
 #[derive(StructOpt)]
@@ -58,18 +144,231 @@ pub struct GenerationData {
     /// counted (if not set, all commits will be counted)
     #[structopt(short, long)]
     author: Option<String>,
+    /// Match the author regex case-insensitively
+    #[structopt(long)]
+    author_ignore_case: bool,
+    /// Also count the commits where the author regex matches one of
+    /// the Co-authored-by trailers in the message (slower, as the
+    /// message bodies have to be read)
+    #[structopt(long)]
+    count_coauthors: bool,
+    /// Report how many of the commits were tiny (under 10 lines
+    /// changed), small (under 100), medium (under 1000) or large, in
+    /// the html and json output (slower, as the changes have to be
+    /// read, and the fast-export streams' commits aren't included)
+    #[structopt(long)]
+    commit_size_buckets: bool,
+    /// A file mapping the authors' aliases to their canonical names,
+    /// one `alias = Name` per line, where the alias is a name or an
+    /// email (the repositories' .mailmap files are also applied)
+    #[structopt(long)]
+    author_map: Option<PathBuf>,
     /// How many subdirectories deep the program should search (if not
     /// set, there is no limit)
     #[structopt(short, long)]
     depth: Option<i32>,
+    /// Skip the symlinked directories when searching for
+    /// repositories, instead of following them
+    #[structopt(long)]
+    no_follow_symlinks: bool,
+    /// Also search the hidden directories (the ones starting with a
+    /// dot) for repositories, which are skipped by default
+    #[structopt(long)]
+    scan_hidden_dirs: bool,
+    /// Stop searching for repositories after this many seconds, and
+    /// continue with the ones found so far
+    #[structopt(long)]
+    scan_timeout: Option<u64>,
+    /// Scan the directories more than N levels below the input
+    /// directories in parallel, and the ones above serially, so that
+    /// deep trees aren't split into lots of tiny tasks (0 parallelizes
+    /// the whole scan, if not set, the scan is serial; requires the
+    /// rayon feature)
+    #[structopt(long)]
+    parallel_below_depth: Option<usize>,
     /// Path(s) to the directory (or directories) containing the
-    /// repositories you want to include
+    /// repositories you want to include, which can also be glob
+    /// patterns (e.g. "~/code/*/repos" or "~/{work,personal}")
     #[structopt(short, long)]
     input: Vec<PathBuf>,
+    /// Graph a single repository on its own, with its name as the
+    /// html page's title and heading. The input can be the repository
+    /// itself (its nested repositories are left out) or a directory
+    /// containing only it
+    #[structopt(long)]
+    single: bool,
+    /// Path(s) to `git fast-export` streams to read commits from, in
+    /// addition to the repositories (- reads the stream from stdin)
+    #[structopt(long, number_of_values = 1)]
+    fast_export: Vec<PathBuf>,
+    /// Only count the commits on each repository's default branch
+    /// (the one origin's HEAD points to, or the checked out branch if
+    /// there's no origin), instead of all of the branches
+    #[structopt(long)]
+    current_branch_only: bool,
+    /// Only follow the first parent of merge commits, counting the
+    /// mainline history of the default branch (implies
+    /// --current-branch-only, as the other branches' commits would be
+    /// counted anyway)
+    #[structopt(long)]
+    first_parent: bool,
     /// Should the git repositories be pulled before analysis
     /// (warning: this will generally increase latency a lot)
     #[structopt(long)]
     pull: bool,
+    /// How many times a failed pull is retried, waiting twice as long
+    /// before each retry (starting from a second)
+    #[structopt(long, default_value = "2")]
+    pull_retries: u32,
+    /// How many of the most recent years should be included in the
+    /// output (if not set, all years with commits are included)
+    #[structopt(long)]
+    last_years: Option<usize>,
+    /// Render the whole history as one continuous grid from the
+    /// first commit to the last, instead of a grid for each year
+    #[structopt(long)]
+    merge_adjacent_years: bool,
+    /// Start every year's grid from January 1st instead of the monday
+    /// before it, so that the same dates line up in the same columns
+    /// across the years (the rows won't correspond to weekdays)
+    #[structopt(long, conflicts_with = "merge-adjacent-years")]
+    align_years: bool,
+    /// Read the commits of this many repositories at a time, adding
+    /// them to the years before moving on to the next batch, which
+    /// keeps the memory use down on very large scans
+    #[structopt(long, conflicts_with_all = &["merge-adjacent-years", "align-years"])]
+    batch_size: Option<usize>,
+    /// Leave out the years without any commits, instead of rendering
+    /// the gaps in the history as empty years
+    #[structopt(long)]
+    skip_empty_years: bool,
+    /// Leave the days of the adjacent years at the start and end of
+    /// each year's grid empty, instead of repeating their commits
+    /// there. The grids are then strictly partitioned by calendar
+    /// year, e.g. for exporting the days, at the cost of the weeks
+    /// crossing the new year looking incomplete
+    #[structopt(long)]
+    no_year_boundary_duplication: bool,
+    /// Highlight the days when a project got its first commit, to
+    /// show when new things were started (only the counted commits
+    /// are considered, so the author filter applies)
+    #[structopt(long)]
+    highlight_first_commits: bool,
+    /// Include the amount of weeks with at least one commit, and the
+    /// longest streak of such weeks, in the stats of each year (a
+    /// gentler measure of consistency than daily streaks)
+    #[structopt(long)]
+    weekly_streaks: bool,
+    /// Include the longest run of days without commits, and when it
+    /// was, in the stats of each year (the days after today aren't
+    /// counted as inactive)
+    #[structopt(long)]
+    show_gaps: bool,
+    /// Should commits dated in the future be counted (by default,
+    /// they're dropped, as they're usually caused by clock skew)
+    #[structopt(long)]
+    include_future_commits: bool,
+    /// Leave out the commits made today, so that the incomplete
+    /// current day doesn't skew the graph
+    #[structopt(long)]
+    exclude_today: bool,
+    /// Treat everything that's skipped as an error: the commits whose
+    /// dates can't be parsed, the repositories whose git log fails, and
+    /// the directories that can't be read are printed out, and the
+    /// program exits with a nonzero status before writing any output
+    /// (ignored by the server, which shouldn't exit on a bad refresh)
+    #[structopt(long)]
+    strict: bool,
+    /// What the project names are based on: the repository's
+    /// directory name ("dir"), its path relative to the input
+    /// directory ("path"), or the url of its origin remote ("remote")
+    #[structopt(long, default_value = "dir", possible_values = &["dir", "path", "remote"])]
+    repo_name_from: find_repositories::NameSource,
+    /// Labels for specific dates, which are shown in the tooltips
+    /// and highlighted in the html (e.g. --annotate
+    /// "2020-05-27=Started a new job")
+    #[structopt(long, number_of_values = 1)]
+    annotate: Vec<Annotation>,
+    /// Regex(es) that exclude the commits whose subject line (the
+    /// first line of the message) matches, e.g. bot commits
+    #[structopt(long, number_of_values = 1)]
+    exclude_message: Vec<Regex>,
+    /// Only count the N most recent commits of each repository, for
+    /// a quick preview of huge repositories (this is a sampling mode:
+    /// the older years will be missing commits, or be left out)
+    #[structopt(long)]
+    max_commits_per_repo: Option<usize>,
+    /// Extra argument(s) appended to the `git log` call that reads
+    /// the commits, for filtering that there's no option for (e.g.
+    /// --git-arg=--grep=fix, or --git-arg=-- --git-arg=src/ for a
+    /// pathspec). They're passed to git as is: a bad argument makes
+    /// git fail for every repository, and the ones that change the
+    /// output format (e.g. --oneline) break the parsing of the commits
+    #[structopt(long, number_of_values = 1, allow_hyphen_values = true)]
+    git_arg: Vec<String>,
+    /// Also write the counted commits into the "commits" table of
+    /// this SQLite database, which is created if it doesn't exist
+    /// (the commits already in the table are skipped, see the README)
+    #[cfg(feature = "sqlite")]
+    #[structopt(long)]
+    sqlite: Option<PathBuf>,
+    /// Only analyze the first N repositories found, in the order of
+    /// their paths, for a quick smoke test against a directory with
+    /// thousands of repositories
+    #[structopt(long)]
+    max_repos: Option<usize>,
+    /// Leave out the repositories without any commits in the last N
+    /// days, e.g. to skip the archived projects
+    #[structopt(long)]
+    active_within: Option<u64>,
+    /// Time the git commands run in each repository, and print out
+    /// the slowest repositories after the scan
+    #[structopt(long)]
+    profile_repos: bool,
+    /// Count each project at most once per day, so that the graph
+    /// shows how many projects were worked on each day instead of
+    /// the amount of commits (the same as --metric active-repos)
+    #[structopt(long)]
+    binary_days: bool,
+    /// What the days are shaded by: the amount of commits
+    /// ("commits"), or the amount of projects with commits
+    /// ("active-repos")
+    #[structopt(long, default_value = "commits", possible_values = &["commits", "active-repos"])]
+    metric: render::Metric,
+    /// Weight the commits by their age when shading the days, halving
+    /// the weight every N days before the end of the grid, to show
+    /// where you've been active lately (note: this makes the graph
+    /// non-stationary, old days fade as new commits are made)
+    #[structopt(long)]
+    recency_halflife: Option<f64>,
+    /// Cap the commit counts used for shading at N, so that outlier
+    /// days (e.g. a big import) get the darkest shade without making
+    /// every other day look faint (the tooltips still show the real
+    /// counts)
+    #[structopt(long)]
+    clip_at: Option<usize>,
+    /// How much time each cell covers: a day ("day"), a week
+    /// ("week"), which shows each year as a single row of weeks, or a
+    /// quarter ("quarter"), which shows each year as four cells
+    #[structopt(long, default_value = "day", possible_values = &["day", "week", "quarter"])]
+    granularity: render::Granularity,
+    /// Which day the commits are counted on: the day in UTC ("utc"),
+    /// or the day in the timezone the commit was made in ("local")
+    #[structopt(long, default_value = "utc", possible_values = &["utc", "local"])]
+    date_bucket: render::DateBucket,
+    /// How git gives out the commit dates: in the timezone they were
+    /// made in ("iso"), or converted into the local timezone ("local",
+    /// which counts the commits on the local days regardless of
+    /// --date-bucket)
+    #[structopt(long, default_value = "iso", possible_values = &["iso", "local"])]
+    git_date_mode: commits::GitDateMode,
+    /// Count each logical commit once, on its author date, even if
+    /// rebasing or amending has left copies of it with different
+    /// hashes reachable from other refs (e.g. stale remote branches).
+    /// The copies are recognized by having the same author, subject
+    /// and author date in the same repository
+    #[structopt(long)]
+    stable_dates: bool,
 }
 
 #[derive(StructOpt, Clone, Default)]
@@ -88,6 +387,124 @@ pub struct ExternalResources {
     /// A css file that will be pasted at the end of the css
     #[structopt(long)]
     external_css: Option<PathBuf>,
+    /// Escape any "</style" in the external css, so that css from an
+    /// untrusted source can't close the <style> element and inject
+    /// markup into the page (the external html files are still
+    /// pasted as they are)
+    #[structopt(long)]
+    escape_external_css: bool,
+    /// The built-in colors of the page and the graph: light, dark, or
+    /// either one depending on the reader's system preference
+    /// ("auto")
+    #[structopt(long, default_value = "auto", possible_values = &["auto", "light", "dark"])]
+    theme: theme::ColorScheme,
+    /// A TOML file defining the background, text and shade level
+    /// colors (see the README for the format)
+    #[structopt(long)]
+    theme_file: Option<PathBuf>,
+    /// Strip the comments and extra whitespace from the css, to make
+    /// the output smaller
+    #[structopt(long)]
+    minify_css: bool,
+    /// A font file (.woff2, .woff, .ttf or .otf) that will be embedded
+    /// into the css and used for the text, so that the graph looks the
+    /// same offline, e.g. when archived (note: no font is bundled, and
+    /// this makes the css about a third bigger than the font file)
+    #[structopt(long)]
+    embed_font: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Clone, Default)]
+pub struct RenderOptions {
+    /// Render the graph as an inline svg instead of html elements
+    /// (only affects html output)
+    #[structopt(long)]
+    svg_inline: bool,
+    /// Render all the years side by side in a single horizontal
+    /// strip, instead of stacking them
+    #[structopt(long)]
+    compact_years: bool,
+    /// Leave a small gap between the months (only affects the html
+    /// tables and the ascii output)
+    #[structopt(long)]
+    month_gaps: bool,
+    /// Leave out the weekday rows without any commits in all of the
+    /// years, e.g. the weekends of a strict work schedule (only
+    /// affects the html tables and the ascii output)
+    #[structopt(long)]
+    hide_empty_weekdays: bool,
+    /// Color each day by the author with the most commits on it,
+    /// with a legend of the authors' colors (only affects html
+    /// output)
+    #[structopt(long)]
+    author_colors: bool,
+    /// Put the total commit count and the years into the page's
+    /// title, e.g. "1,234 commits · 2018–2024" (only affects html
+    /// output)
+    #[structopt(long)]
+    dynamic_title: bool,
+    /// How many of the day's projects are listed in its tooltip, the
+    /// rest are summarized as "and N more"
+    #[structopt(long, default_value = "10")]
+    max_tooltip_projects: usize,
+    /// Show each year's total amount of commits next to the year
+    /// (only affects the html tables)
+    #[structopt(long)]
+    show_year_totals: bool,
+    /// Mark the days with at least this many commits as having met
+    /// the goal, and show how often it was met next to each year
+    /// (only affects the html tables, with --granularity day)
+    #[structopt(long)]
+    daily_goal: Option<usize>,
+    /// How the days padding the grids at the year boundaries are
+    /// shown: not at all ("hidden"), faded ("faint"), or like the
+    /// days without commits ("same-as-empty", the only one that draws
+    /// them in the ascii output, where they're otherwise left blank)
+    #[structopt(long, default_value = "faint", possible_values = &["hidden", "faint", "same-as-empty"])]
+    filler_style: render::FillerStyle,
+    /// Which way the grids are laid out: "horizontal" has a row for
+    /// each weekday, "vertical" a row for each week (only affects the
+    /// html tables and the ascii output)
+    #[structopt(long, default_value = "horizontal", possible_values = &["horizontal", "vertical"])]
+    orientation: render::Orientation,
+}
+
+#[cfg(feature = "server")]
+#[derive(StructOpt)]
+pub struct ServerOptions {
+    /// The address that the server is hosted on [default:
+    /// 127.0.0.1:80]
+    #[structopt(long)]
+    host: Option<SocketAddr>,
+    /// The minimum amount of seconds between regenerating the
+    /// html and css [default: 1]
+    #[structopt(long)]
+    cache_lifetime: Option<u64>,
+    /// A file that will be used as backup storage for the cache
+    /// (useful when you want to keep serving the previous cached
+    /// version after restarting the server, to avoid a period of
+    /// unresponsiveness)
+    #[structopt(long)]
+    cache_file: Option<PathBuf>,
+    /// Use activity-graph/cache in the user's cache directory (e.g.
+    /// ~/.cache on Linux) as the --cache-file
+    #[structopt(long, conflicts_with = "cache-file")]
+    cache_file_auto: bool,
+    /// A token that requests to the /refresh endpoint must
+    /// provide as a query parameter (e.g. /refresh?token=abc) to
+    /// force a cache refresh (if not set, the endpoint is open)
+    #[structopt(long)]
+    refresh_token: Option<String>,
+    /// The path prefix that the server is mounted under, when
+    /// reverse-proxied under a subpath (e.g. /activity)
+    #[structopt(long)]
+    base_path: Option<String>,
+    /// An http:// url that is sent a POST request after each cache
+    /// refresh, with a json body containing the total amount of
+    /// commits and how long the refresh took in milliseconds (e.g.
+    /// {"commits": 1234, "duration_ms": 850})
+    #[structopt(long)]
+    on_refresh_webhook: Option<hyper::Uri>,
 }
 
 #[derive(StructOpt)]
@@ -98,6 +515,11 @@ pub struct Verbosity {
     /// Disables all prints
     #[structopt(short, long)]
     quiet: bool,
+    /// The minimum amount of milliseconds between the updates of the
+    /// verbose progress line, e.g. a high value quiets the updates
+    /// when stderr is redirected into a log
+    #[structopt(long, default_value = "20")]
+    progress_interval: u64,
 }
 
 #[derive(StructOpt)]
@@ -110,6 +532,8 @@ enum CommandArgs {
         gen: GenerationData,
         #[structopt(flatten)]
         ext: ExternalResources,
+        #[structopt(flatten)]
+        opts: RenderOptions,
         /// The file that the resulting html will be printed out to
         #[structopt(short = "o", long, default_value = "activity-graph.html")]
         html: PathBuf,
@@ -117,6 +541,25 @@ enum CommandArgs {
         /// set, it will be included in the html inside a style-element)
         #[structopt(short, long)]
         css: Option<PathBuf>,
+        /// Render everything, but instead of writing the files, print
+        /// out how big they would be
+        #[structopt(long)]
+        dry_run: bool,
+        /// Print a warning if the html and css add up to more than
+        /// this many bytes
+        #[structopt(long)]
+        warn_size: Option<usize>,
+        /// Start the written files with a UTF-8 byte order mark, for
+        /// the tools that detect the encoding by it (the html already
+        /// declares its charset as utf-8)
+        #[structopt(long)]
+        bom: bool,
+        /// Instead of writing out a whole html document, put the graph
+        /// into this existing html file, replacing everything between
+        /// the <!-- activity-graph:start --> and <!-- activity-graph:end -->
+        /// comments (the external html files aren't included)
+        #[structopt(long)]
+        inject_into: Option<PathBuf>,
     },
 
     /// Prints a visualization into stdout
@@ -125,10 +568,212 @@ enum CommandArgs {
         verbosity: Verbosity,
         #[structopt(flatten)]
         gen: GenerationData,
+        #[structopt(flatten)]
+        ext: ExternalResources,
+        #[structopt(flatten)]
+        opts: RenderOptions,
+        /// The format that is printed out: ascii, html, css, svg, json
+        /// or grafana
+        #[structopt(long, default_value = "ascii")]
+        format: output::OutputFormat,
+        /// Whether the output is colored: "auto" colors it when
+        /// printing to a terminal, unless the NO_COLOR environment
+        /// variable is set
+        #[structopt(long, default_value = "auto", possible_values = &["auto", "always", "never"])]
+        color: render::ColorChoice,
+        /// Instead of the visualization, print out the timestamp and
+        /// project of every counted commit, separated by a tab, one per
+        /// line in chronological order
+        #[structopt(long)]
+        dump_timestamps: bool,
+    },
+
+    /// Output a standalone svg image, with the stylesheet embedded
+    Svg {
+        #[structopt(flatten)]
+        verbosity: Verbosity,
+        #[structopt(flatten)]
+        gen: GenerationData,
+        #[structopt(flatten)]
+        ext: ExternalResources,
+        #[structopt(flatten)]
+        opts: RenderOptions,
+        /// The file that the resulting svg will be written to
+        #[structopt(short = "o", long, default_value = "activity-graph.svg")]
+        svg: PathBuf,
+        /// After writing the svg, print out a markdown snippet that
+        /// embeds it, e.g. for pasting into a README
+        #[structopt(long)]
+        emit_embed_snippet: bool,
+        /// The directory that the embed snippet's path is relative to,
+        /// e.g. the README's directory (if not set, the current
+        /// directory)
+        #[structopt(long)]
+        embed_relative_to: Option<PathBuf>,
+        /// Fade the cells in one after another in the order of their
+        /// dates, with css animations (renderers that don't support
+        /// them show the graph as usual)
+        #[structopt(long)]
+        animate: bool,
+    },
+
+    /// Output a small svg badge with the total amount of commits, e.g.
+    /// for a README
+    Badge {
+        #[structopt(flatten)]
+        verbosity: Verbosity,
+        #[structopt(flatten)]
+        gen: GenerationData,
+        /// The file that the resulting svg will be written to
+        #[structopt(short = "o", long, default_value = "activity-badge.svg")]
+        badge: PathBuf,
+        /// The text on the left side of the badge
+        #[structopt(long, default_value = "activity")]
+        label: String,
+        /// The color of the right side of the badge, behind the count
+        #[structopt(long, default_value = "#5c63ff")]
+        color: String,
+    },
+
+    /// Output a "year in review" poster of a single year as html,
+    /// with the year's stats, top projects and busiest week
+    Poster {
+        #[structopt(flatten)]
+        verbosity: Verbosity,
+        #[structopt(flatten)]
+        gen: GenerationData,
+        #[structopt(flatten)]
+        ext: ExternalResources,
+        /// The year the poster is about
+        #[structopt(long)]
+        year: usize,
+        /// The file that the resulting html will be written to
+        #[structopt(short = "o", long, default_value = "activity-poster.html")]
+        poster: PathBuf,
+        /// How many of the projects with the most commits are listed
+        #[structopt(long, default_value = "5")]
+        top_projects: usize,
+    },
+
+    /// Output a comparison of two years as html, with their grids side
+    /// by side and the changes in the commits, active days and longest
+    /// streak
+    Compare {
+        #[structopt(flatten)]
+        verbosity: Verbosity,
+        #[structopt(flatten)]
+        gen: GenerationData,
+        #[structopt(flatten)]
+        ext: ExternalResources,
+        /// The year being compared [default: the current year]
+        #[structopt(long)]
+        year: Option<usize>,
+        /// The year it's compared against [default: the year before
+        /// --year]
+        #[structopt(long)]
+        against: Option<usize>,
+        /// The file that the resulting html will be written to
+        #[structopt(short = "o", long, default_value = "activity-compare.html")]
+        compare: PathBuf,
+    },
+
+    /// Output the commit counts of each day as json
+    Json {
+        #[structopt(flatten)]
+        verbosity: Verbosity,
+        #[structopt(flatten)]
+        gen: GenerationData,
+        /// The file that the json will be written to (if not set, it
+        /// will be printed to stdout)
+        #[structopt(short = "o", long)]
+        json: Option<PathBuf>,
+        /// Write each year into its own file in this directory
+        /// (activity-2021.json, activity-2022.json, ...), along with
+        /// an index.json listing the years and their commit counts
+        #[structopt(long, conflicts_with = "json")]
+        json_split: Option<PathBuf>,
+        /// Output a flat time series of [timestamp_ms, commits] pairs
+        /// for each day instead, for Grafana's json datasources
+        #[structopt(long, conflicts_with_all = &["json-split", "errors-in-output"])]
+        grafana: bool,
+        /// Collect the non-fatal errors into an "errors" array in the
+        /// json, instead of printing them
+        #[structopt(long)]
+        errors_in_output: bool,
+    },
+
+    /// Render a json file written by the json subcommand, without
+    /// reading any repositories
+    ///
+    /// This allows collecting the commits on one machine and rendering
+    /// them on another. The json only has the commit counts of each
+    /// project, so the author colors and commit sizes aren't available.
+    Render {
+        #[structopt(flatten)]
+        verbosity: Verbosity,
+        #[structopt(flatten)]
+        ext: ExternalResources,
+        #[structopt(flatten)]
+        opts: RenderOptions,
+        /// The json file to render (- reads it from stdin)
+        #[structopt(short, long)]
+        input: PathBuf,
+        /// The format that is rendered: ascii, html, css, svg, json or
+        /// grafana
+        #[structopt(long, default_value = "html")]
+        format: output::OutputFormat,
+        /// The file that the output will be written to (if not set, it
+        /// will be printed to stdout)
+        #[structopt(short = "o", long)]
+        output: Option<PathBuf>,
+        /// Whether the output is colored: "auto" colors it when
+        /// printing to a terminal, unless the NO_COLOR environment
+        /// variable is set
+        #[structopt(long, default_value = "auto", possible_values = &["auto", "always", "never"])]
+        color: render::ColorChoice,
+    },
+
+    /// Check that git can be run and that the given paths can be
+    /// read, without scanning for repositories
+    ///
+    /// Prints out whether each check passed, and exits with a nonzero
+    /// status if any of them failed.
+    Check {
+        #[structopt(flatten)]
+        verbosity: Verbosity,
+        #[structopt(flatten)]
+        gen: GenerationData,
+        #[structopt(flatten)]
+        ext: ExternalResources,
+    },
+
+    #[cfg(feature = "pdf")]
+    /// Output a printable pdf, with a section for each year
+    Pdf {
+        #[structopt(flatten)]
+        verbosity: Verbosity,
+        #[structopt(flatten)]
+        gen: GenerationData,
+        /// The file that the resulting pdf will be written to
+        #[structopt(short = "o", long, default_value = "activity-graph.pdf")]
+        pdf: PathBuf,
+        /// The width and height of the day cells, in millimeters
+        #[structopt(long, default_value = "4.5")]
+        cell_size: f64,
+        /// The size of the pages, in millimeters (the default is a
+        /// landscape A4)
+        #[structopt(long, default_value = "297x210")]
+        page_size: pdf::PageSize,
     },
 
     #[cfg(feature = "server")]
     /// Run a server that serves the generated activity graph html
+    ///
+    /// The input directories, author, host and cache lifetime can
+    /// also be set with the ACTIVITY_GRAPH_INPUT (a list of paths,
+    /// separated like PATH), ACTIVITY_GRAPH_AUTHOR, ACTIVITY_GRAPH_HOST
+    /// and ACTIVITY_GRAPH_CACHE_LIFETIME environment variables. The
+    /// command line arguments take precedence.
     Server {
         #[structopt(flatten)]
         verbosity: Verbosity,
@@ -136,22 +781,29 @@ enum CommandArgs {
         gen: GenerationData,
         #[structopt(flatten)]
         ext: ExternalResources,
-        /// The address that the server is hosted on
-        #[structopt(long, default_value = "127.0.0.1:80")]
-        host: SocketAddr,
-        /// The minimum amount of seconds between regenerating the
-        /// html and css
-        #[structopt(long, default_value = "1")]
-        cache_lifetime: u64,
-        /// A file that will be used as backup storage for the cache
-        /// (useful when you want to keep serving the previous cached
-        /// version after restarting the server, to avoid a period of
-        /// unresponsiveness)
+        #[structopt(flatten)]
+        opts: RenderOptions,
+        #[structopt(flatten)]
+        server_opts: ServerOptions,
+    },
+
+    #[cfg(feature = "server")]
+    /// Check that a cache file written by the server is valid, and
+    /// print out the sizes of the html and css stored in it
+    CacheInfo {
+        #[structopt(flatten)]
+        verbosity: Verbosity,
+        /// The cache file to inspect
         #[structopt(long)]
-        cache_file: Option<PathBuf>,
+        cache_file: PathBuf,
     },
 }
 
+/// Prepended to the files written with `--bom`.
+const BYTE_ORDER_MARK: char = '\u{feff}';
+
+// The subcommands are dispatched here, so this grows with them.
+#[allow(clippy::too_many_lines)]
 fn main() {
     let start_time = time::Instant::now();
     let args = Args::from_args();
@@ -162,45 +814,280 @@ fn main() {
                 verbosity,
                 gen,
                 ext,
+                opts,
                 html,
                 css,
+                dry_run,
+                warn_size,
+                bom,
+                inject_into,
             } => {
                 log::set_verbosity(&verbosity);
 
-                let write_to_file = |path: &Path, s: String, name: &str| {
-                    let mut writer = File::create(path).map(BufWriter::new);
-                    match &mut writer {
-                        Ok(writer) => {
-                            if let Err(err) = writer.write(&s.as_bytes()) {
-                                log::println(&format!(
-                                    "error: encountered while writing out the {}: {}",
-                                    name, err
-                                ));
-                            }
+                let years = generate_years(&gen);
+                let output_html = match &inject_into {
+                    Some(path) => injected_html(&ext, &opts, path, css.as_ref(), &years),
+                    None => Some(render::html(&ext, &opts, &html, css.as_ref(), &years)),
+                };
+                let html = inject_into.unwrap_or(html);
+                if let Some(mut output_html) = output_html {
+                    let mut output_css = css.as_ref().map(|_| render::css(&ext, &years));
+                    if bom {
+                        // An injected file might already start with one
+                        if !output_html.starts_with(BYTE_ORDER_MARK) {
+                            output_html.insert(0, BYTE_ORDER_MARK);
                         }
-                        Err(err) => {
-                            log::println(&format!(
-                                "error: encountered while creating the {} file: {}",
-                                name, err
-                            ));
+                        if let Some(output_css) = &mut output_css {
+                            output_css.insert(0, BYTE_ORDER_MARK);
                         }
                     }
+
+                    let size = output_html.len() + output_css.as_ref().map_or(0, String::len);
+                    if warn_size.is_some_and(|warn_size| size > warn_size) {
+                        log::println(&format!(
+                            "warning: the output is {} bytes, which is over the --warn-size",
+                            size
+                        ));
+                    }
+
+                    if dry_run {
+                        println!("html: {} bytes", output_html.len());
+                        if let Some(output_css) = &output_css {
+                            println!("css: {} bytes", output_css.len());
+                        }
+                        println!("years: {}", years.len());
+                        let cells: usize = years.iter().map(|year| year.days.len()).sum();
+                        println!("cells: {}", cells);
+                    } else {
+                        write_to_file(&html, &output_html, "html");
+                        if let (Some(css), Some(output_css)) = (css, output_css) {
+                            write_to_file(&css, &output_css, "css");
+                        }
+                    }
+                }
+            }
+
+            CommandArgs::Stdout {
+                verbosity,
+                gen,
+                ext,
+                opts,
+                format,
+                color,
+                dump_timestamps,
+            } => {
+                log::set_verbosity(&verbosity);
+                if dump_timestamps {
+                    print_timestamps(&gen);
+                } else {
+                    let years = generate_years(&gen);
+                    let out = output::OutputOptions {
+                        ext: &ext,
+                        opts: &opts,
+                        color: color.enabled(),
+                    };
+                    println!("{}", (format.render)(&years, &out));
+                }
+            }
+
+            CommandArgs::Svg {
+                verbosity,
+                gen,
+                ext,
+                opts,
+                svg,
+                emit_embed_snippet,
+                embed_relative_to,
+                animate,
+            } => {
+                log::set_verbosity(&verbosity);
+                let years = generate_years(&gen);
+                let style = render::css(&ext, &years);
+                let options = render::SvgOptions {
+                    animate,
+                    ..render::SvgOptions::from(&opts)
                 };
+                let output_svg = render::svg(&years, None, Some(&style), &options);
+                let written = write_to_file(&svg, &output_svg, "svg");
+                if written && emit_embed_snippet {
+                    println!("{}", embed_snippet(&svg, embed_relative_to.as_deref()));
+                }
+            }
 
+            CommandArgs::Badge {
+                verbosity,
+                gen,
+                badge,
+                label,
+                color,
+            } => {
+                log::set_verbosity(&verbosity);
                 let years = generate_years(&gen);
+                let total = years.iter().map(render::get_total_count).sum();
+                let output_svg = render::badge(&label, total, &color);
+                write_to_file(&badge, &output_svg, "badge");
+            }
 
-                let output_html = render::html(&ext, &html, css.as_ref(), &years);
-                write_to_file(&html, output_html, "html");
+            CommandArgs::Poster {
+                verbosity,
+                gen,
+                ext,
+                year,
+                poster,
+                top_projects,
+            } => {
+                log::set_verbosity(&verbosity);
+                let years = generate_years(&gen);
+                let poster_year = years
+                    .iter()
+                    .find(|y| y.year == year && y.end_year == year)
+                    .filter(|y| render::get_total_count(y) > 0);
+                if let Some(poster_year) = poster_year {
+                    let output_html = poster::render(&ext, poster_year, top_projects);
+                    write_to_file(&poster, &output_html, "poster");
+                } else {
+                    log::error_println(&format!("error: there are no commits in {}", year));
+                }
+            }
 
-                if let Some(css) = css {
-                    let output_css = render::css(&ext);
-                    write_to_file(&css, output_css, "css");
+            CommandArgs::Compare {
+                verbosity,
+                gen,
+                ext,
+                year,
+                against,
+                compare,
+            } => {
+                log::set_verbosity(&verbosity);
+                let year = year.unwrap_or_else(|| Local::today().year() as usize);
+                let Some(against) = against.or_else(|| year.checked_sub(1)) else {
+                    log::error_println(
+                        "error: there's no year before --year 0 to compare it against, set --against",
+                    );
+                    process::exit(1);
+                };
+                let years = generate_years(&gen);
+                let find_year = |year: usize| {
+                    let found = (years.iter()).find(|y| y.year == year && y.end_year == year);
+                    if found.is_none() {
+                        log::error_println(&format!("error: there are no commits in {}", year));
+                    }
+                    found
+                };
+                if let (Some(compared), Some(previous)) = (find_year(year), find_year(against)) {
+                    let output_html = compare::render(&ext, compared, previous);
+                    write_to_file(&compare, &output_html, "comparison");
                 }
             }
 
-            CommandArgs::Stdout { verbosity, gen } => {
+            CommandArgs::Json {
+                verbosity,
+                gen,
+                json,
+                json_split,
+                grafana,
+                errors_in_output,
+            } => {
                 log::set_verbosity(&verbosity);
-                println!("{}", render::ascii(&generate_years(&gen)));
+                if errors_in_output {
+                    log::collect_errors();
+                }
+                let years = generate_years(&gen);
+                let errors = log::take_errors();
+                let errors = if errors_in_output {
+                    Some(&errors[..])
+                } else {
+                    None
+                };
+                if let Some(dir) = json_split {
+                    match fs::create_dir_all(&dir) {
+                        Ok(()) => {
+                            for (file, output_json) in json::render_split(&years, errors) {
+                                write_to_file(&dir.join(file), &output_json, "json");
+                            }
+                        }
+                        Err(err) => log::error_println(&format!(
+                            "error: could not create {}: {}",
+                            dir.display(),
+                            err
+                        )),
+                    }
+                } else {
+                    let output_json = if grafana {
+                        json::render_grafana(&years)
+                    } else {
+                        json::render(&years, errors)
+                    };
+                    match json {
+                        Some(path) => {
+                            write_to_file(&path, &output_json, "json");
+                        }
+                        None => println!("{}", output_json),
+                    }
+                }
+            }
+
+            CommandArgs::Render {
+                verbosity,
+                ext,
+                opts,
+                input,
+                format,
+                output,
+                color,
+            } => {
+                log::set_verbosity(&verbosity);
+                let json = if input == Path::new("-") {
+                    let mut json = String::new();
+                    io::stdin().read_to_string(&mut json).map(|_| json)
+                } else {
+                    fs::read_to_string(&input)
+                };
+                let years = json
+                    .map_err(|err| err.to_string())
+                    .and_then(|json| json::parse(&json));
+                match years {
+                    Ok(years) => {
+                        let out = output::OutputOptions {
+                            ext: &ext,
+                            opts: &opts,
+                            color: output.is_none() && color.enabled(),
+                        };
+                        let rendered = (format.render)(&years, &out);
+                        match output {
+                            Some(path) => {
+                                write_to_file(&path, &rendered, format.name);
+                            }
+                            None => println!("{}", rendered),
+                        }
+                    }
+                    Err(err) => log::error_println(&format!(
+                        "error: could not read the json from {}: {}",
+                        input.display(),
+                        err
+                    )),
+                }
+            }
+
+            CommandArgs::Check {
+                verbosity,
+                gen,
+                ext,
+            } => {
+                log::set_verbosity(&verbosity);
+                check::run(&gen, &ext);
+            }
+
+            #[cfg(feature = "pdf")]
+            CommandArgs::Pdf {
+                verbosity,
+                gen,
+                pdf,
+                cell_size,
+                page_size,
+            } => {
+                log::set_verbosity(&verbosity);
+                pdf::write(&pdf, &generate_years(&gen), cell_size, page_size);
             }
 
             #[cfg(feature = "server")]
@@ -208,12 +1095,20 @@ fn main() {
                 verbosity,
                 gen,
                 ext,
-                host,
-                cache_lifetime,
+                opts,
+                server_opts,
+            } => {
+                log::set_verbosity(&verbosity);
+                server::run(gen, &ext, &opts, server_opts);
+            }
+
+            #[cfg(feature = "server")]
+            CommandArgs::CacheInfo {
+                verbosity,
                 cache_file,
             } => {
                 log::set_verbosity(&verbosity);
-                server::run(&gen, &ext, cache_file, host, cache_lifetime);
+                server::print_cache_info(&cache_file);
             }
         }
     }
@@ -221,14 +1116,425 @@ fn main() {
     log::verbose_println(
         &format!(
             "finished all tasks, this run of the program took {:?}",
-            time::Instant::now() - start_time
+            start_time.elapsed()
         ),
         false,
     );
+
+    let error_count = log::error_count();
+    if error_count > 0 {
+        log::println(&format!("completed with {} errors", error_count));
+        process::exit(1);
+    }
+}
+
+/// Reads the html file and injects the graph into it, returning None
+/// (after printing out the error) if it couldn't be done.
+fn injected_html(
+    ext: &ExternalResources,
+    opts: &RenderOptions,
+    path: &Path,
+    css: Option<&PathBuf>,
+    years: &[Year],
+) -> Option<String> {
+    let injected = fs::read_to_string(path).map_err(|err| err.to_string());
+    let injected =
+        injected.and_then(|document| render::inject_html(ext, opts, &document, path, css, years));
+    match injected {
+        Ok(injected) => Some(injected),
+        Err(err) => {
+            log::error_println(&format!(
+                "error: could not inject the graph into {}: {}",
+                path.display(),
+                err
+            ));
+            None
+        }
+    }
+}
+
+/// Writes the string into the file, returning false (after printing
+/// out the error) if it couldn't be written.
+fn write_to_file(path: &Path, s: &str, name: &str) -> bool {
+    let mut writer = File::create(path).map(BufWriter::new);
+    match &mut writer {
+        Ok(writer) => {
+            if let Err(err) = writer.write_all(s.as_bytes()) {
+                log::error_println(&format!(
+                    "error: encountered while writing out the {}: {}",
+                    name, err
+                ));
+                return false;
+            }
+            true
+        }
+        Err(err) => {
+            log::error_println(&format!(
+                "error: encountered while creating the {} file: {}",
+                name, err
+            ));
+            false
+        }
+    }
+}
+
+/// Returns a markdown image that embeds the file, with the path
+/// relative to the directory (or the current directory).
+fn embed_snippet(path: &Path, relative_to: Option<&Path>) -> String {
+    let absolute = |path: &Path| {
+        path.canonicalize()
+            .or_else(|_| env::current_dir().map(|dir| dir.join(path)))
+            .unwrap_or_else(|_| path.to_path_buf())
+    };
+    let base = absolute(relative_to.unwrap_or_else(|| Path::new(".")));
+    let path = absolute(path);
+    let relative_path = pathdiff::diff_paths(&path, &base).unwrap_or(path);
+    format!(
+        "![Activity graph]({})",
+        render::create_web_path(&relative_path)
+    )
 }
 
 pub fn generate_years(gen: &GenerationData) -> Vec<Year> {
-    let repos = find_repositories::from_paths(&gen.input, gen.depth);
-    let commit_dates = commits::find_dates(gen.author.as_ref(), gen.pull, &repos);
-    render::gather_years(commit_dates)
+    generate_years_with(gen, |_| {})
+}
+
+/// Like `generate_years`, but runs `post_process` on the years just
+/// before they're returned, for custom filtering, annotations or
+/// synthetic days. For example, to leave out a project's commits:
+///
+/// ```ignore
+/// let years = generate_years_with(&gen, |years| {
+///     for day in years.iter_mut().flat_map(|year| year.days.iter_mut()) {
+///         day.commits.retain(|commit| commit.project.name != "dotfiles");
+///     }
+/// });
+/// ```
+pub fn generate_years_with<F: FnMut(&mut Vec<Year>)>(
+    gen: &GenerationData,
+    mut post_process: F,
+) -> Vec<Year> {
+    let errors_before = log::error_count();
+    let repos = find_repos(gen);
+    let author_map = read_author_map(gen);
+    let project = if gen.single {
+        repos.first().map(|repo| repo.name.clone())
+    } else {
+        None
+    };
+
+    let mut first_commits = HashMap::new();
+    let mut years = if let Some(batch_size) = gen.batch_size {
+        let mut accumulator = render::YearAccumulator::new(!gen.no_year_boundary_duplication);
+        for batch in repos.chunks(batch_size.max(1)) {
+            let mut commit_dates = commits::find_dates(gen, batch, &author_map);
+            filter_dates(gen, &mut commit_dates);
+            export_sqlite(gen, &commit_dates);
+            commits::track_first_commits(&mut first_commits, &commit_dates);
+            accumulator.add(commit_dates);
+        }
+        let mut commit_dates = fast_export::find_dates(gen, &author_map);
+        filter_dates(gen, &mut commit_dates);
+        export_sqlite(gen, &commit_dates);
+        commits::track_first_commits(&mut first_commits, &commit_dates);
+        accumulator.add(commit_dates);
+        accumulator.finish()
+    } else {
+        let mut commit_dates = commits::find_dates(gen, &repos, &author_map);
+        commit_dates.extend(fast_export::find_dates(gen, &author_map));
+        filter_dates(gen, &mut commit_dates);
+        export_sqlite(gen, &commit_dates);
+        commits::track_first_commits(&mut first_commits, &commit_dates);
+        gather(gen, commit_dates)
+    };
+    let errors = log::error_count() - errors_before;
+    if gen.strict && errors > 0 {
+        // The errors may have been collected for --errors-in-output,
+        // but there won't be any output to include them in
+        for error in log::take_errors() {
+            log::println(&format!("error: {}", error));
+        }
+        log::println(&format!(
+            "error: stopping because of {} errors, the graph would be incomplete (--strict)",
+            errors
+        ));
+        process::exit(1);
+    }
+    let recency_halflife = gen.recency_halflife.filter(|&halflife| {
+        if halflife > 0.0 {
+            true
+        } else {
+            log::error_println("error: --recency-halflife must be positive, ignoring it");
+            false
+        }
+    });
+    let clip_at = gen.clip_at.filter(|&clip_at| {
+        if clip_at > 0 {
+            true
+        } else {
+            log::error_println("error: --clip-at must be at least 1, ignoring it");
+            false
+        }
+    });
+    for year in &mut years {
+        year.metric = if gen.binary_days {
+            render::Metric::ActiveRepos
+        } else {
+            gen.metric
+        };
+        year.clip_at = clip_at;
+        year.project.clone_from(&project);
+    }
+    // The annotations are matched by date, so they're attached
+    // before the days are aggregated into weeks or quarters
+    render::annotate(&mut years, &gen.annotate);
+    if gen.highlight_first_commits {
+        render::mark_started_projects(&mut years, &first_commits);
+    }
+    if gen.weekly_streaks {
+        for year in &mut years {
+            year.weekly_streaks = Some(render::WeeklyStreaks::for_year(year));
+        }
+    }
+    if gen.show_gaps {
+        for year in &mut years {
+            year.longest_gap = render::LongestGap::for_year(year);
+        }
+    }
+    match gen.granularity {
+        render::Granularity::Day => {}
+        render::Granularity::Week => render::aggregate_weeks(&mut years),
+        render::Granularity::Quarter => render::aggregate_quarters(&mut years),
+    }
+    if gen.skip_empty_years {
+        render::remove_empty_years(&mut years);
+    }
+    if let Some(last_years) = gen.last_years {
+        // The years are in chronological order, so keep the tail
+        let skipped_years = years.len().saturating_sub(last_years);
+        years.drain(..skipped_years);
+    }
+    // The ages are relative to the end of the whole graph, so the
+    // weighting is set up once the displayed years are known
+    if let Some(halflife) = recency_halflife {
+        render::weight_by_recency(&mut years, halflife);
+    }
+    post_process(&mut years);
+    years
+}
+
+/// Finds the repositories in the input paths, sorted by their paths.
+fn find_repos(gen: &GenerationData) -> Vec<ProjectMetadata> {
+    let scan_options = find_repositories::ScanOptions {
+        follow_symlinks: !gen.no_follow_symlinks,
+        scan_hidden_dirs: gen.scan_hidden_dirs,
+        timeout: gen.scan_timeout.map(time::Duration::from_secs),
+        strict: gen.strict,
+        parallel_below_depth: gen.parallel_below_depth,
+    };
+    let input = find_repositories::expand_globs(&gen.input);
+    let repos = find_repositories::from_paths(&input, gen.depth, gen.repo_name_from, &scan_options);
+    // The repositories are sorted so that the commits end up in the
+    // same order on every run, as the days list them in that order
+    let mut repos: Vec<ProjectMetadata> = repos.into_iter().collect();
+    repos.sort_by(|a, b| a.path.cmp(&b.path));
+    if gen.single {
+        // Without the repository, the page would be an empty graph
+        // under no name, so there's nothing worth writing
+        repos = match single_repo(&input, repos) {
+            Ok(repo) => vec![repo],
+            Err(err) => {
+                log::error_println(&format!("error: {}", err));
+                process::exit(1);
+            }
+        };
+    }
+    if let Some(days) = gen.active_within {
+        commits::drop_inactive_repos(&mut repos, days);
+    }
+    if let Some(max_repos) = gen.max_repos {
+        if repos.len() > max_repos {
+            log::println(&format!(
+                "only analyzing the first {} repositories because of --max-repos, skipped {}",
+                max_repos,
+                repos.len() - max_repos
+            ));
+            repos.truncate(max_repos);
+        }
+    }
+    repos
+}
+
+/// Picks the repository to graph with `--single`: the input
+/// directory if it's a repository, or else the only repository in it.
+/// If there's no single repository to pick, returns the error.
+fn single_repo(input: &[PathBuf], repos: Vec<ProjectMetadata>) -> Result<ProjectMetadata, String> {
+    if input.len() != 1 {
+        return Err(format!(
+            "--single needs exactly one input, got {}",
+            input.len()
+        ));
+    }
+    let input = input[0].canonicalize().unwrap_or_else(|_| input[0].clone());
+    if let Some(repo) = repos.iter().find(|repo| repo.path == input) {
+        return Ok(repo.clone());
+    }
+    match repos.len() {
+        1 => Ok(repos.into_iter().next().unwrap()),
+        0 => Err(format!(
+            "--single found no repository in {}",
+            input.display()
+        )),
+        count => {
+            let names: Vec<&str> = repos.iter().map(|repo| repo.name.as_str()).collect();
+            Err(format!(
+                "--single needs one repository, but {} contains {}: {}",
+                input.display(),
+                count,
+                names.join(", ")
+            ))
+        }
+    }
+}
+
+fn read_author_map(gen: &GenerationData) -> commits::AuthorMap {
+    (gen.author_map.as_deref())
+        .map(commits::AuthorMap::from_file)
+        .unwrap_or_default()
+}
+
+/// Prints out the timestamp and project of each counted commit,
+/// separated by a tab, one per line in chronological order.
+fn print_timestamps(gen: &GenerationData) {
+    let author_map = read_author_map(gen);
+    let mut commit_dates = commits::find_dates(gen, &find_repos(gen), &author_map);
+    commit_dates.extend(fast_export::find_dates(gen, &author_map));
+    filter_dates(gen, &mut commit_dates);
+    commit_dates.sort_by_key(|(date, _)| *date);
+    let stdout = io::stdout();
+    let mut stdout = BufWriter::new(stdout.lock());
+    for (date, commit) in &commit_dates {
+        // Stop quietly if the reader has gone away, e.g. head
+        if writeln!(stdout, "{}\t{}", date.to_rfc3339(), commit.project.name).is_err() {
+            return;
+        }
+    }
+    let _ = stdout.flush();
+}
+
+/// Writes the commits into the `--sqlite` database, if there is one.
+#[cfg(feature = "sqlite")]
+fn export_sqlite(gen: &GenerationData, commit_dates: &[(DateTime<FixedOffset>, Commit)]) {
+    if let Some(path) = &gen.sqlite {
+        sqlite::export(path, commit_dates);
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn export_sqlite(_gen: &GenerationData, _commit_dates: &[(DateTime<FixedOffset>, Commit)]) {}
+
+/// Applies the date based filters and conversions to the commits.
+fn filter_dates(gen: &GenerationData, commit_dates: &mut Vec<(DateTime<FixedOffset>, Commit)>) {
+    if gen.date_bucket == render::DateBucket::Utc && gen.git_date_mode == commits::GitDateMode::Iso
+    {
+        commits::convert_to_utc(commit_dates);
+    }
+    if !gen.include_future_commits {
+        commits::drop_future_dates(commit_dates);
+    }
+    if gen.exclude_today {
+        commits::drop_todays_dates(commit_dates);
+    }
+}
+
+/// Gathers the commits into the grids according to the layout options.
+fn gather(
+    gen: &GenerationData,
+    mut commit_dates: Vec<(DateTime<FixedOffset>, Commit)>,
+) -> Vec<Year> {
+    if gen.merge_adjacent_years {
+        if let Some(last_years) = gen.last_years {
+            // There's only one grid, so the older years are left out
+            // before gathering
+            commits::drop_older_years(&mut commit_dates, last_years);
+        }
+        render::gather_continuous(commit_dates)
+    } else if gen.align_years {
+        render::gather_aligned_years(commit_dates)
+    } else {
+        render::gather_years(commit_dates, !gen.no_year_boundary_duplication)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use structopt::StructOpt;
+
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::slice;
+
+    use super::{find_repositories, render, single_repo, Args, CommandArgs};
+    use find_repositories::{NameSource, ScanOptions};
+
+    /// Runs `single_repo` on the repositories found in `input`,
+    /// returning the picked repository's name.
+    fn pick_single(input: &Path) -> Result<String, String> {
+        let options = ScanOptions {
+            follow_symlinks: true,
+            scan_hidden_dirs: false,
+            timeout: None,
+            strict: false,
+            parallel_below_depth: None,
+        };
+        let input = input.to_path_buf();
+        let repos =
+            find_repositories::from_paths(slice::from_ref(&input), None, NameSource::Dir, &options);
+        let mut repos: Vec<_> = repos.into_iter().collect();
+        repos.sort_by(|a, b| a.path.cmp(&b.path));
+        single_repo(slice::from_ref(&input), repos).map(|repo| repo.name)
+    }
+
+    #[test]
+    fn single_picks_the_input_or_its_only_repository() {
+        let root = env::temp_dir().join(format!("activity-graph-single-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("parent/repo/.git")).unwrap();
+        fs::create_dir_all(root.join("outer/.git")).unwrap();
+        fs::create_dir_all(root.join("outer/inner/.git")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+
+        let from_repo = pick_single(&root.join("parent/repo"));
+        let from_parent = pick_single(&root.join("parent"));
+        let from_outer = pick_single(&root.join("outer"));
+        let from_empty = pick_single(&root.join("empty"));
+        let from_root = pick_single(&root);
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(from_repo, Ok(String::from("repo")));
+        assert_eq!(from_parent, Ok(String::from("repo")));
+        assert_eq!(from_outer, Ok(String::from("outer")));
+        assert!(from_empty.is_err());
+        assert!(from_root.is_err());
+    }
+
+    #[test]
+    fn svg_takes_the_shared_render_options() {
+        let args = Args::from_iter_safe(&[
+            "activity-graph",
+            "svg",
+            "--filler-style",
+            "hidden",
+            "--max-tooltip-projects",
+            "3",
+        ])
+        .unwrap();
+        match args.command {
+            Some(CommandArgs::Svg { opts, .. }) => {
+                assert!(opts.filler_style == render::FillerStyle::Hidden);
+                assert_eq!(opts.max_tooltip_projects, 3);
+            }
+            _ => panic!("expected the svg subcommand"),
+        }
+    }
 }
@@ -0,0 +1,78 @@
+//! Contains the table of the output formats that `--format` can name,
+//! which the stdout and render subcommands dispatch through.
+//!
+//! The html, svg and json subcommands aren't routed through it, as
+//! they write files with options of their own (a separate stylesheet,
+//! embed snippets, split json files) that don't fit a format's
+//! `render`. There's no library target either, so the table can't be
+//! extended from outside the crate.
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::render::SvgOptions;
+use crate::{json, render, ExternalResources, RenderOptions, Year};
+
+/// The options available to the output formats.
+pub struct OutputOptions<'a> {
+    pub ext: &'a ExternalResources,
+    pub opts: &'a RenderOptions,
+    /// Whether the output can contain ANSI color escapes.
+    pub color: bool,
+}
+
+/// An output format, which renders the years into a string.
+#[derive(Clone, Copy)]
+pub struct OutputFormat {
+    pub name: &'static str,
+    pub render: fn(&[Year], &OutputOptions) -> String,
+}
+
+/// All of the output formats, for dispatching by name. New formats
+/// only need to be added here to be available to `--format`.
+pub static OUTPUT_FORMATS: &[OutputFormat] = &[
+    OutputFormat {
+        name: "ascii",
+        render: |years, out| render::ascii(out.opts, years, out.color),
+    },
+    OutputFormat {
+        name: "html",
+        render: |years, out| render::html(out.ext, out.opts, Path::new(""), None, years),
+    },
+    OutputFormat {
+        name: "css",
+        render: |years, out| render::css(out.ext, years),
+    },
+    OutputFormat {
+        name: "svg",
+        render: |years, out| {
+            let style = render::css(out.ext, years);
+            render::svg(years, None, Some(&style), &SvgOptions::from(out.opts))
+        },
+    },
+    OutputFormat {
+        name: "json",
+        render: |years, _| json::render(years, None),
+    },
+    OutputFormat {
+        name: "grafana",
+        render: |years, _| json::render_grafana(years),
+    },
+];
+
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        OUTPUT_FORMATS
+            .iter()
+            .find(|format| format.name == s)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<&str> = OUTPUT_FORMATS.iter().map(|format| format.name).collect();
+                format!(
+                    "unknown format: {} (expected one of: {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
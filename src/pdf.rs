@@ -0,0 +1,147 @@
+//! Contains the printable pdf rendering of the visualization.
+use printpdf::{
+    BuiltinFont, Color, IndirectFontRef, Line, Mm, PdfDocument, PdfLayerReference, Point, Rgb,
+};
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::render::{get_label, get_rows, get_total_count, ShadeRamp, SHADE_COLORS};
+use crate::{log, Year};
+
+static MARGIN: f64 = 15.0;
+static HEADER_SIZE: f64 = 14.0;
+static SUMMARY_SIZE: f64 = 9.0;
+
+/// The size of a pdf page in millimeters, parsed from e.g. "297x210".
+#[derive(Clone, Copy)]
+pub struct PageSize {
+    width: f64,
+    height: f64,
+}
+
+impl FromStr for PageSize {
+    type Err = String;
+    fn from_str(s: &str) -> Result<PageSize, String> {
+        let mut parts = s.splitn(2, 'x').map(str::parse::<f64>);
+        match (parts.next(), parts.next()) {
+            (Some(Ok(width)), Some(Ok(height))) if width > 0.0 && height > 0.0 => {
+                Ok(PageSize { width, height })
+            }
+            _ => Err(format!("expected WIDTHxHEIGHT in millimeters, got: {}", s)),
+        }
+    }
+}
+
+/// Writes a pdf with each year in its own section, newest first,
+/// adding pages as needed.
+pub fn write(path: &Path, years: &[Year], cell_size: f64, page_size: PageSize) {
+    log::verbose_println("rendering pdf...", true);
+    let (width, height) = (Mm(page_size.width), Mm(page_size.height));
+    let (doc, page, layer) = PdfDocument::new("Activity", width, height, "Layer 1");
+    let font = match doc.add_builtin_font(BuiltinFont::Helvetica) {
+        Ok(font) => font,
+        Err(err) => {
+            log::error_println(&format!("error: could not load the pdf font: {}", err));
+            return;
+        }
+    };
+
+    let rows = years.first().map_or(7, get_rows) as f64;
+    let section_height = HEADER_SIZE + SUMMARY_SIZE + rows * cell_size + MARGIN;
+    let mut layer = doc.get_page(page).get_layer(layer);
+    let mut y = page_size.height - MARGIN;
+    for year in years.iter().rev() {
+        if y - section_height < 0.0 && y < page_size.height - MARGIN {
+            let (page, new_layer) = doc.add_page(width, height, "Layer 1");
+            layer = doc.get_page(page).get_layer(new_layer);
+            y = page_size.height - MARGIN;
+        }
+        y = draw_year(&layer, &font, year, cell_size, y);
+    }
+
+    let file = File::create(path).map(BufWriter::new);
+    let result = file
+        .map_err(|err| err.to_string())
+        .and_then(|mut file| doc.save(&mut file).map_err(|err| err.to_string()));
+    match result {
+        Ok(()) => log::verbose_println("rendered pdf", false),
+        Err(err) => log::error_println(&format!("error: could not write the pdf: {}", err)),
+    }
+}
+
+/// Draws the year's header, summary and grid starting from `y` (the
+/// distance from the bottom of the page), returning where the next
+/// section should start.
+fn draw_year(
+    layer: &PdfLayerReference,
+    font: &IndirectFontRef,
+    year: &Year,
+    cell_size: f64,
+    mut y: f64,
+) -> f64 {
+    let ramp = ShadeRamp::for_year(year);
+    let active_days = year
+        .days
+        .iter()
+        .filter(|day| !day.filler && !day.commits.is_empty())
+        .count();
+    // Not the ramp's max count, which is capped by --clip-at
+    let max_count = (year.days.iter())
+        .filter(|day| !day.filler)
+        .map(|day| day.commits.len())
+        .max()
+        .unwrap_or(0);
+
+    layer.set_fill_color(rgb((0x44, 0x44, 0x44)));
+    y -= HEADER_SIZE * 0.5;
+    layer.use_text(get_label(year), HEADER_SIZE, Mm(MARGIN), Mm(y), font);
+    y -= SUMMARY_SIZE * 0.6;
+    let summary = format!(
+        "{} commits, {} active days, at most {} commits in a day",
+        get_total_count(year),
+        active_days,
+        max_count
+    );
+    layer.use_text(summary, SUMMARY_SIZE, Mm(MARGIN), Mm(y), font);
+    y -= SUMMARY_SIZE * 0.4;
+
+    let rows = get_rows(year);
+    for day in 0..rows {
+        for week in 0..year.weeks {
+            let index = day * year.weeks + week;
+            if year.days[index].filler {
+                continue;
+            }
+            let shade = ramp.day_level(year, index);
+            layer.set_fill_color(rgb(SHADE_COLORS[shade]));
+            let x = MARGIN + week as f64 * cell_size;
+            let top = y - day as f64 * cell_size;
+            layer.add_shape(square(x, top, cell_size * 0.85));
+        }
+    }
+    y - rows as f64 * cell_size - MARGIN
+}
+
+fn square(x: f64, top: f64, size: f64) -> Line {
+    let points = vec![
+        (Point::new(Mm(x), Mm(top)), false),
+        (Point::new(Mm(x + size), Mm(top)), false),
+        (Point::new(Mm(x + size), Mm(top - size)), false),
+        (Point::new(Mm(x), Mm(top - size)), false),
+    ];
+    Line {
+        points,
+        is_closed: true,
+        has_fill: true,
+        has_stroke: false,
+        is_clipping_path: false,
+    }
+}
+
+fn rgb((r, g, b): (u8, u8, u8)) -> Color {
+    let channel = |c: u8| f64::from(c) / 255.0;
+    Color::Rgb(Rgb::new(channel(r), channel(g), channel(b), None))
+}
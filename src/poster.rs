@@ -0,0 +1,174 @@
+//! Contains the "year in review" poster of the `poster` subcommand: a
+//! standalone html page with a single year's grid and stats.
+use chrono::{Duration, NaiveDate};
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::render::{self, cell_date, escape_html, get_label, get_total_count, SvgOptions};
+use crate::{ExternalResources, Year};
+
+static POSTER_CSS: &str = "
+.poster {
+    text-align: center;
+    margin-top: 3em;
+}
+
+.poster h1 {
+    font-size: 2.5rem;
+    margin-bottom: 0.25em;
+}
+
+.poster-stats {
+    display: flex;
+    justify-content: space-around;
+    margin: 2em 0;
+}
+
+.poster-stats strong {
+    display: block;
+    font-size: 1.75rem;
+}
+
+.poster-projects {
+    list-style: none;
+    padding: 0;
+}
+";
+
+/// Renders the poster of the year, with the busiest week highlighted
+/// in the grid and the `top_projects` projects with the most commits
+/// listed under it.
+pub fn render(ext: &ExternalResources, year: &Year, top_projects: usize) -> String {
+    let mut year = year.clone();
+    let busiest_week = highlight_busiest_week(&mut year);
+    let days = || year.days.iter().filter(|day| !day.filler);
+    let active_days = days().filter(|day| !day.commits.is_empty()).count();
+    let busiest_day = (0..year.days.len())
+        .filter(|&i| !year.days[i].filler)
+        .map(|i| (cell_date(&year, i), year.days[i].commits.len()))
+        .filter(|&(_, commits)| commits > 0)
+        .max_by_key(|&(date, commits)| (commits, Reverse(date)));
+
+    let mut projects: HashMap<&str, usize> = HashMap::new();
+    for commit in days().flat_map(|day| &day.commits) {
+        *projects.entry(&commit.project.name).or_insert(0) += 1;
+    }
+    let mut projects: Vec<(&str, usize)> = projects.into_iter().collect();
+    projects.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then(a_name.cmp(b_name)));
+
+    let mut stats = vec![
+        (get_total_count(&year).to_string(), String::from("commits")),
+        (active_days.to_string(), String::from("active days")),
+    ];
+    if let Some((date, commits)) = busiest_day {
+        stats.push((
+            commits.to_string(),
+            format!("commits on the busiest day, {}", date.format("%B %-d")),
+        ));
+    }
+    if let Some(streaks) = year.weekly_streaks {
+        stats.push((
+            streaks.active_weeks.to_string(),
+            String::from("active weeks"),
+        ));
+        stats.push((
+            streaks.longest_streak.to_string(),
+            String::from("weeks in the longest weekly streak"),
+        ));
+    }
+    if let Some(gap) = year.longest_gap {
+        stats.push((
+            gap.days.to_string(),
+            format!(
+                "days in the longest gap, from {} to {}",
+                gap.start.format("%B %-d"),
+                gap.end.format("%B %-d")
+            ),
+        ));
+    }
+    if let Some((monday, commits)) = busiest_week {
+        stats.push((
+            commits.to_string(),
+            format!("commits in the busiest week, {}", monday.format("%B %-d")),
+        ));
+    }
+
+    let mut result = String::with_capacity(1024);
+    result += &format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width,initial-scale=1\">\n<title>{0} in review</title>\n<style>\n{1}{2}</style>\n</head>\n<body>\n<div class=\"poster\">\n<h1>{0} in review</h1>\n",
+        get_label(&year),
+        render::css(ext, std::slice::from_ref(&year)),
+        POSTER_CSS
+    );
+    result += "<div class=\"poster-stats\">\n";
+    for (value, label) in &stats {
+        result += &format!(" <div><strong>{}</strong>{}</div>\n", value, label);
+    }
+    result += "</div>\n";
+    result += &render::svg(
+        std::slice::from_ref(&year),
+        None,
+        None,
+        &SvgOptions::EMBEDDED,
+    );
+    if top_projects > 0 && !projects.is_empty() {
+        result += "<h2>Top projects</h2>\n<ol class=\"poster-projects\">\n";
+        for (project, commits) in projects.iter().take(top_projects) {
+            result += &format!(" <li>{}: {} commits</li>\n", escape_html(project), commits);
+        }
+        result += "</ol>\n";
+    }
+    result += "</div>\n</body></html>\n";
+    result
+}
+
+/// Annotates the days of the week with the most commits, so that
+/// they're outlined in the grid. Returns the monday of the week and
+/// its commit count, or None if the year has no commits.
+fn highlight_busiest_week(year: &mut Year) -> Option<(NaiveDate, usize)> {
+    let rows = render::get_rows(year);
+    let week_commits = |week: usize| -> usize {
+        (0..rows)
+            .map(|row| &year.days[row * year.weeks + week])
+            .filter(|day| !day.filler)
+            .map(|day| day.commits.len())
+            .sum()
+    };
+    let (busiest_week, commits) = (0..year.weeks)
+        .map(|week| (week, week_commits(week)))
+        .max_by_key(|&(week, commits)| (commits, Reverse(week)))?;
+    if commits == 0 {
+        return None;
+    }
+    for row in 0..rows {
+        let day = &mut year.days[row * year.weeks + busiest_week];
+        if !day.filler {
+            day.annotations.push(String::from("The busiest week"));
+        }
+    }
+    Some((
+        year.first_day + Duration::days(busiest_week as i64 * 7),
+        commits,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::highlight_busiest_week;
+    use crate::render::{self, tests::commits_on};
+
+    #[test]
+    fn busiest_week_is_highlighted() {
+        let commits = commits_on(&[(2021, 3, 1), (2021, 6, 8), (2021, 6, 9), (2021, 6, 13)]);
+        let mut year = render::gather_years(commits, true).remove(0);
+        let busiest_week = highlight_busiest_week(&mut year);
+        assert_eq!(busiest_week, Some((NaiveDate::from_ymd(2021, 6, 7), 3)));
+        let highlighted = (year.days.iter())
+            .filter(|day| !day.annotations.is_empty())
+            .count();
+        assert_eq!(highlighted, 7);
+    }
+}
@@ -1,278 +1,2312 @@
 //! Contains the functionality to render the visualizations out of
 //! dated commit data.
 use chrono::naive::NaiveDate;
-use chrono::{DateTime, Datelike, Utc};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local};
 
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader, IsTerminal, Read};
 use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
 
-use crate::{log, Day, ExternalResources, ProjectMetadata, Year};
+use crate::theme::Theme;
+use crate::{
+    log, Annotation, Commit, Day, ExternalResources, ProjectMetadata, RenderOptions, Year,
+};
 
 static HTML_HEAD: &str = include_str!("head.html");
 static CSS: &str = include_str!("activity-graph.css");
-static WEEKS: usize = 53;
+static INJECT_START: &str = "<!-- activity-graph:start -->";
+static INJECT_END: &str = "<!-- activity-graph:end -->";
+// The cells are only hidden while their animation is waiting to start,
+// so renderers without css animations show the static graph
+static SVG_ANIMATION_CSS: &str = "
+@keyframes activity-fade-in {
+    from { opacity: 0; }
+}
 
-pub fn gather_years(mut commit_dates: Vec<(DateTime<Utc>, ProjectMetadata)>) -> Vec<Year> {
-    if commit_dates.is_empty() {
-        return Vec::new();
+.activity-svg .animated {
+    animation: activity-fade-in 0.4s ease-out backwards;
+}
+
+@media (prefers-reduced-motion: reduce) {
+    .activity-svg .animated {
+        animation: none;
     }
+}
+";
+/// How long it takes for `--animate` to reach the last cell.
+const ANIMATION_SECONDS: f64 = 4.0;
+pub static WEEKS: usize = 53;
+/// The light mode palette from activity-graph.css, for the renderers
+/// that don't use the stylesheet.
+pub static SHADE_COLORS: [(u8, u8, u8); ShadeRamp::LEVELS] = [
+    (0xf5, 0xf6, 0xff),
+    (0xc0, 0xc1, 0xff),
+    (0xa0, 0xa3, 0xff),
+    (0x7f, 0x84, 0xff),
+    (0x5c, 0x63, 0xff),
+];
 
-    commit_dates.sort_by(|(a, _), (b, _)| a.cmp(b));
-
-    let get_year = |date: DateTime<Utc>| date.date().year() as usize;
-    let first_year = get_year(commit_dates[0].0);
-    let last_year = get_year(commit_dates[commit_dates.len() - 1].0);
-
-    // Years is a vec containing vecs of years, which consist
-    // of weekday-major grids of days: eg. the first row
-    // represents all of the mondays in the year, in order.
-    let mut years = Vec::with_capacity(last_year - first_year + 1);
-    for year in first_year..=last_year {
-        years.push(Year {
-            year,
-            days: vec![Day::default(); WEEKS * 7],
-        });
-    }
-
-    let mut commit_dates = commit_dates.into_iter().peekable();
-    let mut counted_commits = 0;
-    for year in first_year..=last_year {
-        // Loop through the years
-
-        let weekday_offset = NaiveDate::from_ymd(year as i32, 1, 1)
-            .weekday()
-            .num_days_from_monday() as usize;
-        let last_day =
-            weekday_offset + NaiveDate::from_ymd(year as i32 + 1, 1, 1).pred().ordinal() as usize;
-        let last_week = (last_day - (last_day % 7)) / 7;
-
-        let (before, after) = years.split_at_mut(year - first_year + 1);
-        let (before, current) = before.split_at_mut(before.len() - 1);
-        let days = &mut current[0].days;
-        let mut last_year_days = if year > first_year {
-            Some(&mut before[before.len() - 1].days)
-        } else {
-            None
-        };
-        let mut next_year_days = if year < last_year {
-            Some(&mut after[0].days)
-        } else {
-            None
-        };
-        while let Some((date, _)) = commit_dates.peek() {
-            // Loop through the days until the commit is from
-            // next year or commits run out
+/// Gathers the commits into a grid for each year. If
+/// `duplicate_boundaries` is true, the filler days at the start and
+/// end of each grid show the adjacent years' commits.
+pub fn gather_years(
+    commit_dates: Vec<(DateTime<FixedOffset>, Commit)>,
+    duplicate_boundaries: bool,
+) -> Vec<Year> {
+    let mut accumulator = YearAccumulator::new(duplicate_boundaries);
+    accumulator.add(commit_dates);
+    accumulator.finish()
+}
 
-            if date.year() != year as i32 {
-                break;
-            }
+/// Gathers the commits into a grid for each year, like
+/// `gather_years`, but in batches, so that all of the commits don't
+/// need to be in memory at once.
+pub struct YearAccumulator {
+    years: BTreeMap<usize, Year>,
+    counted_commits: usize,
+    duplicate_boundaries: bool,
+}
 
-            let ordinal_with_offset = date.ordinal0() as usize + weekday_offset;
-            let weekday_index = ordinal_with_offset % 7;
-            let week_index = ordinal_with_offset / 7;
-            if week_index < WEEKS {
-                let day = &mut days[weekday_index * WEEKS + week_index];
-                // This branch should always be taken because of the peek()
-                if let Some((_, metadata)) = commit_dates.next() {
-                    // Add the commit to the next/last year as well,
-                    // to achieve consistency in the duplicated days
-                    if week_index == last_week {
-                        if let Some(days) = &mut next_year_days {
-                            let next_year_today = &mut days[weekday_index * WEEKS];
-                            next_year_today.commits.push(metadata.clone());
-                        }
-                    }
-                    if week_index == 0 {
-                        if let Some(days) = &mut last_year_days {
-                            let last_year_today = &mut days[weekday_index * WEEKS + WEEKS - 1];
-                            last_year_today.commits.push(metadata.clone());
-                        }
-                    }
-                    day.commits.push(metadata);
-                    counted_commits += 1;
-                }
-            }
+impl YearAccumulator {
+    /// Creates an empty accumulator. If `duplicate_boundaries` is
+    /// false, the filler days at the year boundaries are left empty,
+    /// instead of showing the adjacent years' commits.
+    pub fn new(duplicate_boundaries: bool) -> YearAccumulator {
+        YearAccumulator {
+            years: BTreeMap::new(),
+            counted_commits: 0,
+            duplicate_boundaries,
         }
+    }
 
-        // Set the first and last days as filler
-        let first_day = weekday_offset;
-        for ordinal_with_offset in (0..first_day).chain(last_day..days.len()) {
-            let weekday_index = ordinal_with_offset % 7;
-            let week_index = ordinal_with_offset / 7;
-            days[weekday_index * WEEKS + week_index].filler = true;
+    /// Adds the commits into their years' grids. The days at the year
+    /// boundaries are duplicated into the adjacent years in `finish`,
+    /// unless disabled in `new`.
+    pub fn add(&mut self, mut commit_dates: Vec<(DateTime<FixedOffset>, Commit)>) {
+        // The days are in each commit's own timezone, so they're
+        // sorted by the local time to keep the days' commits in order
+        commit_dates.sort_by_key(|(date, _)| date.naive_local());
+        for (date, commit) in commit_dates {
+            let date = date.naive_local().date();
+            let (weekday_index, week_index) = get_cell_position(date);
+            let year = date.year() as usize;
+            let grid = self.years.entry(year).or_insert_with(|| empty_year(year));
+            grid.days[weekday_index * grid.weeks + week_index]
+                .commits
+                .push(commit);
+            self.counted_commits += 1;
         }
-
         log::verbose_println(
             &format!(
-                "prepared year {} for rendering, {} commits processed so far",
-                year, counted_commits
+                "gathered the commits into years, {} commits processed so far",
+                self.counted_commits
             ),
             true,
         );
     }
 
-    let year_range = if first_year == last_year {
-        format!(" {}", first_year)
-    } else {
-        format!("s {}-{}", first_year, last_year)
+    /// Returns the years from the first year with commits to the
+    /// last, with the days at the year boundaries duplicated into the
+    /// adjacent years' filler days.
+    pub fn finish(self) -> Vec<Year> {
+        let (Some(&first_year), Some(&last_year)) =
+            (self.years.keys().next(), self.years.keys().next_back())
+        else {
+            return Vec::new();
+        };
+        let YearAccumulator {
+            years: mut gathered,
+            counted_commits,
+            duplicate_boundaries,
+        } = self;
+        let mut years: Vec<Year> = (first_year..=last_year)
+            .map(|year| gathered.remove(&year).unwrap_or_else(|| empty_year(year)))
+            .collect();
+        if duplicate_boundaries {
+            for i in 1..years.len() {
+                let (before, after) = years.split_at_mut(i);
+                let (last_year, year) = (&mut before[i - 1], &mut after[0]);
+                copy_into_filler_days(last_year, year);
+                copy_into_filler_days(year, last_year);
+            }
+        }
+
+        // The filler days only contain duplicates of the adjacent
+        // years' days, so if this doesn't hold, commits were hidden.
+        debug_assert_eq!(
+            counted_commits,
+            years.iter().map(get_total_count).sum::<usize>()
+        );
+
+        let year_range = if first_year == last_year {
+            format!(" {}", first_year)
+        } else {
+            format!("s {}-{}", first_year, last_year)
+        };
+        log::verbose_println(
+            &format!(
+                "prepared year{} for rendering, {} commits processed",
+                year_range, counted_commits
+            ),
+            false,
+        );
+        years
+    }
+}
+
+/// Copies the commits of `from`'s days into the filler days of `to`
+/// with the same dates.
+fn copy_into_filler_days(from: &Year, to: &mut Year) {
+    for index in 0..to.days.len() {
+        if !to.days[index].filler {
+            continue;
+        }
+        let days_from_first_day = (cell_date(to, index) - from.first_day).num_days();
+        if !(0..(from.days.len() as i64)).contains(&days_from_first_day) {
+            continue;
+        }
+        let days_from_first_day = days_from_first_day as usize;
+        let (weekday_index, week_index) = (days_from_first_day % 7, days_from_first_day / 7);
+        let source = &from.days[weekday_index * from.weeks + week_index];
+        if !source.filler {
+            let commits = source.commits.clone();
+            to.days[index].commits.extend(commits);
+        }
+    }
+}
+
+/// Returns the year's grid without any commits, starting from the
+/// monday of the week with the first day of the year. The days
+/// outside of the year are marked as filler. The grid has `WEEKS`
+/// weeks, except for the leap years starting on a sunday, whose last
+/// day is in a 54th week.
+fn empty_year(year: usize) -> Year {
+    let first_day = NaiveDate::from_ymd(year as i32, 1, 1);
+    let weekday_offset = first_day.weekday().num_days_from_monday();
+    let last_day = NaiveDate::from_ymd(year as i32, 12, 31);
+    let (_, last_week) = get_cell_position(last_day);
+    let weeks = last_week + 1;
+    let mut empty_year = Year {
+        year,
+        end_year: year,
+        weeks,
+        first_day: first_day - Duration::days(i64::from(weekday_offset)),
+        days: vec![Day::default(); weeks * 7],
+        metric: Metric::default(),
+        recency: None,
+        clip_at: None,
+        granularity: Granularity::Day,
+        weekly_streaks: None,
+        longest_gap: None,
+        project: None,
+    };
+    for index in 0..empty_year.days.len() {
+        empty_year.days[index].filler = cell_date(&empty_year, index).year() as usize != year;
+    }
+    empty_year
+}
+
+/// Gathers the commits into a single grid spanning from the first
+/// commit to the last, instead of a grid for each year. Every date has
+/// exactly one cell, so nothing is duplicated at the year boundaries.
+pub fn gather_continuous(mut commit_dates: Vec<(DateTime<FixedOffset>, Commit)>) -> Vec<Year> {
+    if commit_dates.is_empty() {
+        return Vec::new();
+    }
+
+    commit_dates.sort_by_key(|(date, _)| date.naive_local());
+    let first_date = commit_dates[0].0.naive_local().date();
+    let last_date = commit_dates[commit_dates.len() - 1].0.naive_local().date();
+    let weekday_offset = first_date.weekday().num_days_from_monday();
+    let first_day = first_date - Duration::days(i64::from(weekday_offset));
+    let weeks = (last_date - first_day).num_days() as usize / 7 + 1;
+
+    let mut year = Year {
+        year: first_date.year() as usize,
+        end_year: last_date.year() as usize,
+        weeks,
+        first_day,
+        days: vec![Day::default(); weeks * 7],
+        metric: Metric::default(),
+        recency: None,
+        clip_at: None,
+        granularity: Granularity::Day,
+        weekly_streaks: None,
+        longest_gap: None,
+        project: None,
     };
+    for index in 0..year.days.len() {
+        let date = cell_date(&year, index);
+        year.days[index].filler = date < first_date || date > last_date;
+    }
+
+    let commit_count = commit_dates.len();
+    for (date, commit) in commit_dates {
+        let days_from_first_day = (date.naive_local().date() - first_day).num_days() as usize;
+        let (weekday_index, week_index) = (days_from_first_day % 7, days_from_first_day / 7);
+        year.days[weekday_index * weeks + week_index]
+            .commits
+            .push(commit);
+    }
+
     log::verbose_println(
         &format!(
-            "prepared year{} for rendering, {} commits processed",
-            year_range, counted_commits
+            "prepared {} weeks for rendering, {} commits processed",
+            weeks, commit_count
         ),
         false,
     );
+    vec![year]
+}
+
+/// Gathers the commits into a grid for each year like `gather_years`,
+/// but with every grid starting from January 1st instead of the
+/// monday before it. This way the same dates are in the same columns
+/// in every year, at the cost of the rows not being weekdays. The
+/// cells past the end of the year are left empty, as those days are
+/// at the start of the next year's grid.
+pub fn gather_aligned_years(mut commit_dates: Vec<(DateTime<FixedOffset>, Commit)>) -> Vec<Year> {
+    if commit_dates.is_empty() {
+        return Vec::new();
+    }
+
+    commit_dates.sort_by_key(|(date, _)| date.naive_local());
+    let get_year = |date: &DateTime<FixedOffset>| date.naive_local().year() as usize;
+    let first_year = get_year(&commit_dates[0].0);
+    let last_year = get_year(&commit_dates[commit_dates.len() - 1].0);
+
+    let mut years: Vec<Year> = (first_year..=last_year)
+        .map(|year| {
+            let mut year = empty_year(year);
+            // Starting from the first day, every year fits in `WEEKS`
+            year.weeks = WEEKS;
+            year.days = vec![Day::default(); WEEKS * 7];
+            year.first_day = NaiveDate::from_ymd(year.year as i32, 1, 1);
+            for index in 0..year.days.len() {
+                year.days[index].filler = cell_date(&year, index).year() as usize != year.year;
+            }
+            year
+        })
+        .collect();
+
+    let commit_count = commit_dates.len();
+    for (date, commit) in commit_dates {
+        let year = &mut years[get_year(&date) - first_year];
+        let ordinal = date.naive_local().ordinal0() as usize;
+        let (weekday_index, week_index) = (ordinal % 7, ordinal / 7);
+        year.days[weekday_index * WEEKS + week_index]
+            .commits
+            .push(commit);
+    }
 
+    log::verbose_println(
+        &format!(
+            "prepared years {}-{} for rendering, {} commits processed",
+            first_year, last_year, commit_count
+        ),
+        false,
+    );
     years
 }
 
+/// Attaches the annotations' labels to the days they refer to,
+/// including the duplicated days in the adjacent years.
+pub fn annotate(years: &mut [Year], annotations: &[Annotation]) {
+    if annotations.is_empty() {
+        return;
+    }
+    for year in years {
+        for i in 0..year.days.len() {
+            let date = cell_date(year, i);
+            for annotation in annotations.iter().filter(|a| a.date == date) {
+                year.days[i].annotations.push(annotation.label.clone());
+            }
+        }
+    }
+}
+
+/// Counts the projects started on each day from the dates of their
+/// first commits, including the duplicated days in the adjacent years.
+pub fn mark_started_projects(
+    years: &mut [Year],
+    first_commits: &HashMap<ProjectMetadata, NaiveDate>,
+) {
+    let mut started: HashMap<NaiveDate, usize> = HashMap::new();
+    for date in first_commits.values() {
+        *started.entry(*date).or_insert(0) += 1;
+    }
+    for year in years {
+        for i in 0..year.days.len() {
+            let date = cell_date(year, i);
+            year.days[i].started_projects = started.get(&date).copied().unwrap_or(0);
+        }
+    }
+}
+
+/// Determines what the counts of each day represent.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Metric {
+    /// The amount of commits.
+    #[default]
+    Commits,
+    /// The amount of distinct projects with commits.
+    ActiveRepos,
+}
+
+impl Metric {
+    /// Returns the day's count by this metric, which is what its shade
+    /// is based on.
+    pub fn count(self, day: &Day) -> usize {
+        match self {
+            Metric::Commits => day.commits.len(),
+            Metric::ActiveRepos => {
+                let projects: HashSet<&PathBuf> = day
+                    .commits
+                    .iter()
+                    .map(|commit| &commit.project.path)
+                    .collect();
+                projects.len()
+            }
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Metric, String> {
+        match s {
+            "commits" => Ok(Metric::Commits),
+            "active-repos" => Ok(Metric::ActiveRepos),
+            _ => Err(format!("unknown metric: {}", s)),
+        }
+    }
+}
+
+/// Determines how much time each cell of the grid covers.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Granularity {
+    #[default]
+    Day,
+    Week,
+    Quarter,
+}
+
+impl FromStr for Granularity {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Granularity, String> {
+        match s {
+            "day" => Ok(Granularity::Day),
+            "week" => Ok(Granularity::Week),
+            "quarter" => Ok(Granularity::Quarter),
+            _ => Err(format!("unknown granularity: {}", s)),
+        }
+    }
+}
+
+/// Determines which timezone's day each commit is counted on.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum DateBucket {
+    /// The day in UTC.
+    #[default]
+    Utc,
+    /// The day in the timezone of the commit's own date.
+    Local,
+}
+
+impl FromStr for DateBucket {
+    type Err = String;
+    fn from_str(s: &str) -> Result<DateBucket, String> {
+        match s {
+            "utc" => Ok(DateBucket::Utc),
+            "local" => Ok(DateBucket::Local),
+            _ => Err(format!("unknown date bucket: {}", s)),
+        }
+    }
+}
+
+/// Determines how the filler days, which pad the grids to full
+/// weeks at the year boundaries, are rendered.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum FillerStyle {
+    /// Not shown at all (the cells still take up space in the grid).
+    Hidden,
+    /// Shown in a faded out version of their shade.
+    #[default]
+    Faint,
+    /// Shown like the days without commits.
+    SameAsEmpty,
+}
+
+impl FromStr for FillerStyle {
+    type Err = String;
+    fn from_str(s: &str) -> Result<FillerStyle, String> {
+        match s {
+            "hidden" => Ok(FillerStyle::Hidden),
+            "faint" => Ok(FillerStyle::Faint),
+            "same-as-empty" => Ok(FillerStyle::SameAsEmpty),
+            _ => Err(format!("unknown filler style: {}", s)),
+        }
+    }
+}
+
+/// The options of `svg`, which are the ones it shares with
+/// `RenderOptions`, and the svg subcommand's `--animate`.
+#[derive(Clone, Copy)]
+pub struct SvgOptions {
+    pub max_tooltip_projects: usize,
+    pub filler_style: FillerStyle,
+    /// Fade the cells in in the order of their dates.
+    pub animate: bool,
+}
+
+impl SvgOptions {
+    /// The options for the grids that are parts of bigger pages, like
+    /// the posters and comparisons, which leave out the filler days.
+    pub const EMBEDDED: SvgOptions = SvgOptions {
+        max_tooltip_projects: 10,
+        filler_style: FillerStyle::Hidden,
+        animate: false,
+    };
+}
+
+impl From<&RenderOptions> for SvgOptions {
+    fn from(opts: &RenderOptions) -> SvgOptions {
+        SvgOptions {
+            max_tooltip_projects: opts.max_tooltip_projects,
+            filler_style: opts.filler_style,
+            animate: false,
+        }
+    }
+}
+
+/// Determines which way the grids are laid out.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Orientation {
+    /// A row for each weekday, with the weeks from left to right.
+    #[default]
+    Horizontal,
+    /// A row for each week, with the weeks from top to bottom.
+    Vertical,
+}
+
+impl FromStr for Orientation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Orientation, String> {
+        match s {
+            "horizontal" => Ok(Orientation::Horizontal),
+            "vertical" => Ok(Orientation::Vertical),
+            _ => Err(format!("unknown orientation: {}", s)),
+        }
+    }
+}
+
+impl FillerStyle {
+    /// Returns the shade and the extra classes of the day's cell in
+    /// the html and svg.
+    fn apply(self, day: &Day, shade: usize) -> (usize, &'static str) {
+        match self {
+            _ if !day.filler => (shade, ""),
+            FillerStyle::Hidden => (shade, " filler-day filler-hidden"),
+            FillerStyle::Faint => (shade, " filler-day"),
+            FillerStyle::SameAsEmpty => (0, ""),
+        }
+    }
+}
+
+/// Collapses the weekday rows of each year into a single row, where
+/// each cell contains the commits of the whole week. The commits on
+/// the filler days are left out, so they aren't counted twice.
+pub fn aggregate_weeks(years: &mut [Year]) {
+    for year in years {
+        let mut weeks = vec![Day::default(); year.weeks];
+        for (week, aggregate) in weeks.iter_mut().enumerate() {
+            aggregate.filler = true;
+            for day in (0..get_rows(year)).map(|row| &year.days[row * year.weeks + week]) {
+                // The filler days belong to the adjacent years
+                if !day.filler {
+                    aggregate.filler = false;
+                    aggregate.commits.extend(day.commits.iter().cloned());
+                    aggregate
+                        .annotations
+                        .extend(day.annotations.iter().cloned());
+                    aggregate.started_projects += day.started_projects;
+                }
+            }
+        }
+        year.days = weeks;
+        year.granularity = Granularity::Week;
+    }
+}
+
+/// Collapses each year into a single row of four cells, where each
+/// cell contains the commits of a whole quarter. The continuous grid
+/// gets four cells for each of the years it covers.
+pub fn aggregate_quarters(years: &mut [Year]) {
+    for year in years {
+        let mut quarters = vec![Day::default(); (year.end_year - year.year + 1) * 4];
+        for quarter in &mut quarters {
+            quarter.filler = true;
+        }
+        for (index, day) in year.days.iter().enumerate() {
+            if day.filler {
+                continue;
+            }
+            let date = cell_date(year, index);
+            let quarter =
+                &mut quarters[(date.year() as usize - year.year) * 4 + date.month0() as usize / 3];
+            quarter.filler = false;
+            quarter.commits.extend(day.commits.iter().cloned());
+            quarter.annotations.extend(day.annotations.iter().cloned());
+            quarter.started_projects += day.started_projects;
+        }
+        year.weeks = quarters.len();
+        year.days = quarters;
+        year.granularity = Granularity::Quarter;
+    }
+}
+
+/// Returns the label of the quarter at `index` in the year's
+/// aggregated cells, e.g. "Q1", with the year included for the
+/// continuous grid.
+fn quarter_label(year: &Year, index: usize) -> String {
+    if year.end_year == year.year {
+        format!("Q{}", index % 4 + 1)
+    } else {
+        format!("Q{} {}", index % 4 + 1, year.year + index / 4)
+    }
+}
+
+/// Returns the amount of rows in the year's grid: 7 for the
+/// weekdays, or 1 when aggregated by week or quarter.
+pub fn get_rows(year: &Year) -> usize {
+    year.days.len() / year.weeks
+}
+
+/// Returns the year for the headers, or the range of years for the
+/// continuous grid.
+pub fn get_label(year: &Year) -> String {
+    if year.end_year == year.year {
+        year.year.to_string()
+    } else {
+        format!("{}-{}", year.year, year.end_year)
+    }
+}
+
+/// Shades the days of the years by their weight in `Recency`.
+pub fn weight_by_recency(years: &mut [Year], halflife: f64) {
+    let recency = Recency::for_years(years, halflife);
+    for year in years {
+        year.recency = recency;
+    }
+}
+
+/// Removes the years without any commits of their own. The filler
+/// days don't count, as they only have copies of the adjacent years'
+/// commits.
+pub fn remove_empty_years(years: &mut Vec<Year>) {
+    years.retain(|year| get_total_count(year) > 0);
+}
+
+/// Returns the (weekday, week) position of the date in its year's
+/// grid. The week can be `WEEKS` for the last day of a leap year that
+/// starts on a sunday, which has a 54th week for it.
+fn get_cell_position(date: NaiveDate) -> (usize, usize) {
+    let weekday_offset = NaiveDate::from_ymd(date.year(), 1, 1)
+        .weekday()
+        .num_days_from_monday() as usize;
+    let ordinal_with_offset = date.ordinal0() as usize + weekday_offset;
+    (ordinal_with_offset % 7, ordinal_with_offset / 7)
+}
+
+/// Returns the date that the day at `index` in the year's `days`
+/// represents. The filler days get dates from the adjacent years. The
+/// quarters are represented by their first days.
+pub fn cell_date(year: &Year, index: usize) -> NaiveDate {
+    if year.granularity == Granularity::Quarter {
+        let (years, quarter) = (index / 4, index % 4);
+        return NaiveDate::from_ymd((year.year + years) as i32, quarter as u32 * 3 + 1, 1);
+    }
+    let (weekday_index, week_index) = (index / year.weeks, index % year.weeks);
+    year.first_day + Duration::days((week_index * 7 + weekday_index) as i64)
+}
+
 /// Renders a HTML visualization of the commits based on the
 /// arguments.
 pub fn html(
     ext: &ExternalResources,
-    html_path: &PathBuf,
+    opts: &RenderOptions,
+    html_path: &Path,
     css_path: Option<&PathBuf>,
     years: &[Year],
 ) -> String {
     // Prepare the html scaffolding around the tables
-    let external_head = read_optional_file(&ext.external_head).unwrap_or_else(String::new);
-    let external_header = read_optional_file(&ext.external_header).unwrap_or_else(String::new);
-    let external_footer = read_optional_file(&ext.external_footer).unwrap_or_else(String::new);
-    let external_css = read_optional_file(&ext.external_css).unwrap_or_else(String::new);
+    let external_head = read_optional_file(ext.external_head.as_ref()).unwrap_or_default();
+    let external_header = read_optional_file(ext.external_header.as_ref()).unwrap_or_default();
+    let external_footer = read_optional_file(ext.external_footer.as_ref()).unwrap_or_default();
 
-    let mut style = None;
-    if let (Some(base), Some(css_path)) = (html_path.parent(), &css_path) {
-        if let Some(relative_path) = pathdiff::diff_paths(&css_path, base) {
-            // Add the <link> element instead of <style> if using external css
-            let path = create_web_path(&relative_path);
-            style = Some(format!("<link href=\"{}\" rel=\"stylesheet\">", path));
-        }
-    }
-    if style.is_none() {
-        style = Some(format!("<style>\n{}\n{}</style>", CSS, external_css));
-    }
-    let style = style.unwrap();
+    let style = style_element(ext, html_path, css_path, years);
+    // A single repository's graph is titled after the repository
+    let project = years.first().and_then(|year| year.project.as_deref());
+    let title = match (project, opts.dynamic_title) {
+        (Some(project), true) => Some(format!(
+            "{} · {}",
+            escape_html(project),
+            dynamic_title(years)
+        )),
+        (Some(project), false) => Some(escape_html(project)),
+        (None, true) => Some(dynamic_title(years)),
+        (None, false) => None,
+    };
+    let html_head = match title {
+        Some(title) => HTML_HEAD.replace(
+            "<title>Activity</title>",
+            &format!("<title>{}</title>", title),
+        ),
+        None => HTML_HEAD.to_string(),
+    };
 
     let head = format!(
         "<!DOCTYPE html>\n<html>\n<head>\n{}\n{}\n{}\n</head>\n<body>\n{}\n",
-        HTML_HEAD, style, external_head, external_header
+        html_head, style, external_head, external_header
     );
     let tail = format!("{}</body></html>", external_footer);
 
-    // Render the tables
+    // Render the graph
     let mut result = String::with_capacity(1024);
     log::verbose_println("rendering html...", true);
     result += &head;
-    for year in years.iter().rev() {
-        let max_count = get_max_count(year);
+    if let Some(project) = project {
         result += &format!(
-            "<div class=\"activity-table\" title=\"A table containing the commit visualization for {}.\">\n<div class=\"activity-header-year\" colspan=\"{}\">{}</div>\n <div>\n",
-            year.year, WEEKS, year.year
+            "<h1 class=\"activity-heading\">{}</h1>\n",
+            escape_html(project)
         );
-        for day in 0..7 {
-            result += "  <div class=\"blob-row\">";
-            for week in 0..WEEKS {
-                let metadata = &year.days[day * WEEKS + week];
-                let commit_count = metadata.commits.len();
-                let shade = get_shade_class(commit_count, max_count);
-                let tooltip = if commit_count == 0 {
-                    String::from("No commits")
-                } else {
-                    format!("{} commits", commit_count)
-                };
-                let filler = if metadata.filler { "filler-day" } else { "" };
-                result += &format!(
-                    "<span class=\"blob lvl{} {}\" title=\"{}\"></span>",
-                    shade, filler, tooltip
-                );
-            }
-            result += " </div>\n";
-        }
-        result += " </div>\n</div>\n";
     }
+    result += &graph(opts, years);
     result += &tail;
     log::verbose_println("rendered html", false);
     result
 }
 
-pub fn css(ext: &ExternalResources) -> String {
-    let external_css = read_optional_file(&ext.external_css).unwrap_or_else(String::new);
-    format!("{}\n{}", CSS, external_css)
+/// Returns the page title for `--dynamic-title`, with the total commit
+/// count and the years covered, e.g. "1,234 commits · 2018–2024".
+fn dynamic_title(years: &[Year]) -> String {
+    let commits: usize = years.iter().map(get_total_count).sum();
+    let first = years.iter().map(|year| year.year).min();
+    let last = years.iter().map(|year| year.end_year).max();
+    let commits = format!("{} commits", group_thousands(commits));
+    match (first, last) {
+        (Some(first), Some(last)) if first == last => format!("{} · {}", commits, first),
+        (Some(first), Some(last)) => format!("{} · {}–{}", commits, first, last),
+        _ => commits,
+    }
 }
 
-/// Renders an ASCII visualization of the commits.
-pub fn ascii(years: &[Year]) -> String {
-    let mut result = String::with_capacity(512);
-    log::verbose_println("rendering ascii visualization...", true);
-    for year in years.iter().rev() {
-        let max_count = get_max_count(year);
-        result.push('\n');
-        for day in 0..7 {
-            for week in 0..WEEKS {
-                let metadata = &year.days[day * WEEKS + week];
-                if metadata.filler {
-                    result.push(' ');
-                } else {
-                    let shade = metadata.commits.len() as f32 / max_count as f32;
-                    result.push(get_shaded_char(shade));
-                }
-            }
-            result.push('\n');
+/// Renders the graph and its stylesheet as a html fragment, and puts
+/// it between the INJECT_START and INJECT_END markers in `document`,
+/// replacing whatever was between them. Returns an error if the
+/// markers aren't found.
+pub fn inject_html(
+    ext: &ExternalResources,
+    opts: &RenderOptions,
+    document: &str,
+    html_path: &Path,
+    css_path: Option<&PathBuf>,
+    years: &[Year],
+) -> Result<String, String> {
+    let start = document
+        .find(INJECT_START)
+        .map(|start| start + INJECT_START.len())
+        .ok_or_else(|| format!("could not find the {} marker", INJECT_START))?;
+    let end = document[start..]
+        .find(INJECT_END)
+        .map(|end| start + end)
+        .ok_or_else(|| format!("could not find the {} marker after the start", INJECT_END))?;
+    let style = style_element(ext, html_path, css_path, years);
+    Ok(format!(
+        "{}\n{}\n{}{}",
+        &document[..start],
+        style,
+        graph(opts, years),
+        &document[end..]
+    ))
+}
+
+/// Returns the <link> element for the external css, or the <style>
+/// element containing the css if there is none.
+fn style_element(
+    ext: &ExternalResources,
+    html_path: &Path,
+    css_path: Option<&PathBuf>,
+    years: &[Year],
+) -> String {
+    if let (Some(base), Some(css_path)) = (html_path.parent(), &css_path) {
+        if let Some(relative_path) = pathdiff::diff_paths(css_path, base) {
+            // Add the <link> element instead of <style> if using external css
+            let path = create_web_path(&relative_path);
+            return format!("<link href=\"{}\" rel=\"stylesheet\">", path);
         }
     }
-    log::verbose_println("rendered ascii visualization", false);
-    result
+    format!("<style>\n{}</style>", css(ext, years))
 }
 
-fn create_web_path(path: &Path) -> String {
-    path.components()
-        .filter_map(|component| match component {
-            Component::Normal(s) => s.to_str(),
-            Component::CurDir => Some("."),
-            Component::ParentDir => Some(".."),
-            _ => None,
-        })
-        .fold(String::new(), |mut a, b| {
-            a += b;
-            a
-        })
+/// Renders the graph itself, as the html tables or an inline svg.
+fn graph(opts: &RenderOptions, years: &[Year]) -> String {
+    let mut result = String::with_capacity(1024);
+    let author_colors = if opts.author_colors {
+        Some(AuthorColors::new(years))
+    } else {
+        None
+    };
+    if opts.svg_inline {
+        result += &svg(years, author_colors.as_ref(), None, &SvgOptions::from(opts));
+    } else {
+        result += &html_tables(opts, years, author_colors.as_ref());
+    }
+    if let Some(author_colors) = &author_colors {
+        result += &author_colors.legend();
+    }
+    if let Some(sizes) = commit_sizes(years) {
+        result += "<div class=\"commit-sizes\">Commit sizes:";
+        for ((name, limit), count) in COMMIT_SIZE_BUCKETS.iter().zip(&sizes) {
+            result += &match limit {
+                Some(limit) => format!(" {} {} (under {} lines),", count, name, limit),
+                None => format!(" {} {}", count, name),
+            };
+        }
+        result += "</div>\n";
+    }
+    result
 }
 
-fn read_optional_file(path: &Option<PathBuf>) -> Option<String> {
-    let path = path.as_ref()?;
-    let file = File::open(path).ok()?;
-    let mut reader = BufReader::new(file);
-    let mut result = Vec::new();
-    reader.read_to_end(&mut result).ok()?;
-    String::from_utf8(result).ok()
+fn html_tables(opts: &RenderOptions, years: &[Year], colors: Option<&AuthorColors>) -> String {
+    let mut result = String::with_capacity(1024);
+    let rows = displayed_rows(opts, years);
+    if opts.compact_years {
+        // All the years side by side, oldest first
+        result += "<div class=\"activity-strip\">\n";
+        for year in years {
+            result += &html_table(opts, year, &rows, colors);
+        }
+        result += "</div>\n";
+    } else {
+        for year in years.iter().rev() {
+            result += &html_table(opts, year, &rows, colors);
+        }
+    }
+    result
 }
 
-fn get_max_count(year: &Year) -> usize {
-    year.days
+/// Returns the rows of the grids that are rendered: all of them, or
+/// with `--hide-empty-weekdays`, the ones with commits in any of the
+/// years. The rows are hidden from every year, so that they line up.
+fn displayed_rows(opts: &RenderOptions, years: &[Year]) -> Vec<usize> {
+    let all_rows: Vec<usize> = (0..years.first().map_or(7, get_rows)).collect();
+    if !opts.hide_empty_weekdays {
+        return all_rows;
+    }
+    // The filler days only have copies of the adjacent years' commits
+    let has_commits = |row: usize| {
+        years.iter().any(|year| {
+            (0..year.weeks).any(|week| {
+                let day = &year.days[row * year.weeks + week];
+                !day.filler && !day.commits.is_empty()
+            })
+        })
+    };
+    let rows: Vec<usize> = all_rows
         .iter()
-        .map(|metadata| metadata.commits.len())
-        .max()
-        .unwrap_or(0)
-        .max(1)
-}
-
-fn get_shade_class(commits: usize, max_count: usize) -> usize {
-    let norm = commits as f32 / max_count as f32;
-    match norm {
-        x if x == 0.0 => 0,
-        x if x < 0.25 => 1,
-        x if x < 0.5 => 2,
-        x if x < 0.75 => 3,
-        _ => 4,
+        .copied()
+        .filter(|&row| has_commits(row))
+        .collect();
+    // Without any commits, there'd be nothing left to show
+    if rows.is_empty() {
+        all_rows
+    } else {
+        rows
     }
 }
 
-fn get_shaded_char(shade: f32) -> char {
-    match shade {
-        x if x > 0.5 => '\u{2593}',
-        x if x > 0.0 => '\u{2592}',
-        _ => '\u{2591}',
+fn html_table(
+    opts: &RenderOptions,
+    year: &Year,
+    rows: &[usize],
+    colors: Option<&AuthorColors>,
+) -> String {
+    let mut result = String::with_capacity(1024);
+    let ramp = ShadeRamp::for_year(year);
+    result += &format!(
+        "<div class=\"activity-table\" title=\"A table containing the commit visualization for {}.\">\n<div class=\"activity-header-year\" colspan=\"{}\">{}</div>\n <div>\n",
+        get_label(year),
+        year.weeks,
+        table_header(opts, year)
+    );
+    let cell = |index: usize| -> String {
+        let metadata = &year.days[index];
+        let commit_count = metadata.commits.len();
+        let (shade, filler) = opts
+            .filler_style
+            .apply(metadata, ramp.day_level(year, index));
+        let tooltip = get_tooltip(year.metric, metadata, opts.max_tooltip_projects);
+        let annotated = if metadata.annotations.is_empty() {
+            ""
+        } else {
+            " annotated"
+        };
+        let started = if metadata.started_projects > 0 {
+            " started-project"
+        } else {
+            ""
+        };
+        let met_goal = match daily_goal(opts, year) {
+            Some(goal) if commit_count >= goal => " met-goal",
+            _ => "",
+        };
+        let style = colors
+            .and_then(|colors| colors.style(metadata, shade, "background-color"))
+            .unwrap_or_default();
+        format!(
+            "<span class=\"blob lvl{}{}{}{}{}\"{} title=\"{}\"></span>",
+            shade, filler, annotated, started, met_goal, style, tooltip
+        )
+    };
+    let label = |week: usize| -> String {
+        if year.granularity == Granularity::Quarter {
+            format!(
+                "<span class=\"quarter-label\">{}</span>",
+                quarter_label(year, week)
+            )
+        } else {
+            String::new()
+        }
+    };
+    match opts.orientation {
+        Orientation::Horizontal => {
+            for &day in rows {
+                result += "  <div class=\"blob-row\">";
+                for week in 0..year.weeks {
+                    if opts.month_gaps && starts_month(year, week) {
+                        result += "<span class=\"month-gap\"></span>";
+                    }
+                    result += &cell(day * year.weeks + week);
+                }
+                result += " </div>\n";
+            }
+            if year.granularity == Granularity::Quarter {
+                result += "  <div class=\"blob-row\">";
+                for week in 0..year.weeks {
+                    result += &label(week);
+                }
+                result += " </div>\n";
+            }
+        }
+        Orientation::Vertical => {
+            for week in 0..year.weeks {
+                if opts.month_gaps && starts_month(year, week) {
+                    result += "  <div class=\"month-gap-row\"></div>\n";
+                }
+                result += "  <div class=\"blob-row\">";
+                for &day in rows {
+                    result += &cell(day * year.weeks + week);
+                }
+                result += &label(week);
+                result += " </div>\n";
+            }
+        }
+    }
+    result += " </div>\n</div>\n";
+    result
+}
+
+/// Returns the year's label, followed by the stats enabled in the
+/// options.
+fn table_header(opts: &RenderOptions, year: &Year) -> String {
+    let mut header = get_label(year);
+    if opts.show_year_totals {
+        header += &match year.metric {
+            Metric::Commits => format!(" \u{2014} {} commits", get_total_count(year)),
+            Metric::ActiveRepos => {
+                let repo_days: usize = (year.days.iter())
+                    .filter(|day| !day.filler)
+                    .map(|day| Metric::ActiveRepos.count(day))
+                    .sum();
+                format!(" \u{2014} {} active repo-days", repo_days)
+            }
+        };
+    }
+    if let Some(streaks) = year.weekly_streaks {
+        header += &format!(
+            " \u{2014} {} active weeks, longest streak of {} weeks",
+            streaks.active_weeks, streaks.longest_streak
+        );
+    }
+    if let Some(gap) = year.longest_gap {
+        header += &format!(
+            " \u{2014} longest gap of {} days ({} \u{2013} {})",
+            gap.days,
+            gap.start.format("%b %-d"),
+            gap.end.format("%b %-d")
+        );
+    }
+    if let Some(goal) = daily_goal(opts, year) {
+        if let Some(attainment) = goal_attainment(year, goal) {
+            header += &format!(
+                " \u{2014} goal met on {:.0}% of the days",
+                attainment * 100.0
+            );
+        }
+    }
+    header
+}
+
+/// Returns the `--daily-goal`, unless the year's cells are weeks or
+/// quarters, which almost all would meet a daily goal.
+fn daily_goal(opts: &RenderOptions, year: &Year) -> Option<usize> {
+    opts.daily_goal
+        .filter(|_| year.granularity == Granularity::Day)
+}
+
+/// Returns the fraction of the year's days up to today that had at
+/// least `goal` commits, or None if the year hasn't started yet.
+fn goal_attainment(year: &Year, goal: usize) -> Option<f32> {
+    let today = Local::now().naive_local().date();
+    let days: Vec<&Day> = (0..year.days.len())
+        .filter(|&i| !year.days[i].filler && cell_date(year, i) <= today)
+        .map(|i| &year.days[i])
+        .collect();
+    if days.is_empty() {
+        return None;
+    }
+    let met = days.iter().filter(|day| day.commits.len() >= goal).count();
+    Some(met as f32 / days.len() as f32)
+}
+
+/// Renders an SVG visualization of the commits. The shades are
+/// applied with the same classes as in the html, so the svg is meant
+/// to be styled by the stylesheet from `css`, which can be embedded
+/// with `style` for a standalone svg file.
+pub fn svg(
+    years: &[Year],
+    colors: Option<&AuthorColors>,
+    style: Option<&str>,
+    options: &SvgOptions,
+) -> String {
+    const CELL: usize = 12;
+    const HEADER: usize = 30;
+    let rows = years.first().map_or(7, get_rows);
+    // The quarters get a row of labels under the cells
+    let label_rows = years.first().map_or(0, |year| {
+        usize::from(year.granularity == Granularity::Quarter)
+    });
+    let year_height = HEADER + (rows + label_rows) * CELL;
+    let weeks = years.iter().map(|year| year.weeks).max().unwrap_or(WEEKS);
+    let (width, height) = (weeks * CELL, years.len() * year_height);
+
+    let mut result = String::with_capacity(1024);
+    log::verbose_println("rendering svg...", true);
+    result += &format!(
+        "<svg class=\"activity-svg\" xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+        width, height
+    );
+    if let Some(style) = style {
+        result += &format!("<style>\n{}</style>\n", style);
+    }
+    let date_range = if options.animate {
+        result += &format!("<style>{}</style>\n", SVG_ANIMATION_CSS);
+        date_range(years)
+    } else {
+        None
+    };
+    for (i, year) in years.iter().rev().enumerate() {
+        let ramp = ShadeRamp::for_year(year);
+        let y = i * year_height;
+        result += &format!(
+            " <text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n",
+            width / 2,
+            y + HEADER * 2 / 3,
+            get_label(year)
+        );
+        for day in 0..get_rows(year) {
+            for week in 0..year.weeks {
+                let index = day * year.weeks + week;
+                let metadata = &year.days[index];
+                let (shade, filler) = options
+                    .filler_style
+                    .apply(metadata, ramp.day_level(year, index));
+                let annotated = if metadata.annotations.is_empty() {
+                    ""
+                } else {
+                    " annotated"
+                };
+                let started = if metadata.started_projects > 0 {
+                    " started-project"
+                } else {
+                    ""
+                };
+                let mut style = colors
+                    .and_then(|colors| colors.style(metadata, shade, "fill"))
+                    .unwrap_or_default();
+                let mut animated = "";
+                if let Some((first, last)) = date_range {
+                    let delay = animation_delay(cell_date(year, index), first, last);
+                    let delay = format!("animation-delay: {:.2}s", delay);
+                    style = if style.is_empty() {
+                        format!(" style=\"{}\"", delay)
+                    } else {
+                        format!("{}; {}\"", style.trim_end_matches('"'), delay)
+                    };
+                    animated = " animated";
+                }
+                result += &format!(
+                    " <rect class=\"lvl{}{}{}{}{}\"{} x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"><title>{}</title></rect>\n",
+                    shade,
+                    filler,
+                    annotated,
+                    started,
+                    animated,
+                    style,
+                    week * CELL + 1,
+                    y + HEADER + day * CELL + 1,
+                    CELL - 2,
+                    CELL - 2,
+                    get_tooltip(year.metric, metadata, options.max_tooltip_projects)
+                );
+            }
+        }
+        if year.granularity == Granularity::Quarter {
+            for week in 0..year.weeks {
+                result += &format!(
+                    " <text class=\"quarter-label\" x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n",
+                    week * CELL + CELL / 2,
+                    y + HEADER + (rows + 1) * CELL - 2,
+                    quarter_label(year, week)
+                );
+            }
+        }
+    }
+    result += "</svg>\n";
+    log::verbose_println("rendered svg", false);
+    result
+}
+
+/// Returns the first and last dates of the non-filler cells.
+fn date_range(years: &[Year]) -> Option<(NaiveDate, NaiveDate)> {
+    let dates = years.iter().flat_map(|year| {
+        (0..year.days.len())
+            .filter(move |&i| !year.days[i].filler)
+            .map(move |i| cell_date(year, i))
+    });
+    dates.fold(None, |range, date| match range {
+        Some((first, last)) => Some((date.min(first), date.max(last))),
+        None => Some((date, date)),
+    })
+}
+
+/// Returns the seconds after which the cell of `date` starts fading
+/// in, proportional to its place between `first` and `last`.
+fn animation_delay(date: NaiveDate, first: NaiveDate, last: NaiveDate) -> f64 {
+    let span = (last - first).num_days().max(1) as f64;
+    let days = (date - first).num_days().max(0) as f64;
+    (days / span).min(1.0) * ANIMATION_SECONDS
+}
+
+/// Renders a shields.io-style badge, with the label on the left and
+/// the commit count on the right, on a background of `color`.
+pub fn badge(label: &str, commits: usize, color: &str) -> String {
+    // Roughly the average width of a character in 11px Verdana
+    const CHAR_WIDTH: usize = 7;
+    const PADDING: usize = 6;
+    let value = format!("{} commits", group_thousands(commits));
+    let label_width = label.chars().count() * CHAR_WIDTH + PADDING * 2;
+    let value_width = value.chars().count() * CHAR_WIDTH + PADDING * 2;
+    let width = label_width + value_width;
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"20\" role=\"img\" aria-label=\"{label}: {value}\">
+ <title>{label}: {value}</title>
+ <clipPath id=\"round\"><rect width=\"{width}\" height=\"20\" rx=\"3\"/></clipPath>
+ <g clip-path=\"url(#round)\">
+  <rect width=\"{label_width}\" height=\"20\" fill=\"#555\"/>
+  <rect x=\"{label_width}\" width=\"{value_width}\" height=\"20\" fill=\"{color}\"/>
+ </g>
+ <g fill=\"#fff\" text-anchor=\"middle\" font-family=\"Verdana,Geneva,DejaVu Sans,sans-serif\" font-size=\"11\">
+  <text x=\"{label_x}\" y=\"14\">{label}</text>
+  <text x=\"{value_x}\" y=\"14\">{value}</text>
+ </g>
+</svg>
+",
+        width = width,
+        label_width = label_width,
+        value_width = value_width,
+        label_x = label_width / 2,
+        value_x = label_width + value_width / 2,
+        label = escape_html(label),
+        value = value,
+        color = escape_html(color),
+    )
+}
+
+/// Formats the number with commas between each group of three
+/// digits, e.g. 1,234.
+fn group_thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut result = String::with_capacity(digits.len() * 4 / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            result.push(',');
+        }
+        result.push(digit);
+    }
+    result
+}
+
+/// Distinct colors for the authors, the most active author first. The
+/// authors past the end of the palette share the last color.
+static AUTHOR_PALETTE: [&str; 9] = [
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+    "#9c755f",
+];
+
+/// The colors assigned to the authors, for shading the days by the
+/// author with the most commits on them.
+pub struct AuthorColors {
+    /// The authors with their commit counts and colors, the most
+    /// active first.
+    authors: Vec<(String, usize, &'static str)>,
+}
+
+impl AuthorColors {
+    pub fn new(years: &[Year]) -> AuthorColors {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let days = years.iter().flat_map(|year| year.days.iter());
+        for commit in days.filter(|day| !day.filler).flat_map(|day| &day.commits) {
+            *counts.entry(&commit.author).or_insert(0) += 1;
+        }
+        let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+        counts.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then(a_name.cmp(b_name)));
+        let authors = counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, (author, count))| {
+                let color = AUTHOR_PALETTE[i.min(AUTHOR_PALETTE.len() - 1)];
+                (author.to_string(), count, color)
+            })
+            .collect();
+        AuthorColors { authors }
+    }
+
+    /// Returns the style attribute that colors the day by its most
+    /// active author, with the shade level as the opacity.
+    fn style(&self, day: &Day, shade: usize, property: &str) -> Option<String> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for commit in &day.commits {
+            *counts.entry(&commit.author).or_insert(0) += 1;
+        }
+        // Ties go to the author who's more active overall, as
+        // max_by_key picks the last of the equal elements
+        let (_, _, color) = self
+            .authors
+            .iter()
+            .rev()
+            .filter(|(author, _, _)| counts.contains_key(author.as_str()))
+            .max_by_key(|(author, _, _)| counts[author.as_str()])?;
+        let opacity = 0.4 + 0.15 * shade as f32;
+        Some(format!(
+            " style=\"{}: {}; opacity: {:.2}\"",
+            property, color, opacity
+        ))
+    }
+
+    /// Renders the legend listing the authors and their colors.
+    fn legend(&self) -> String {
+        let mut result = String::from("<div class=\"author-legend\">\n");
+        for (author, count, color) in &self.authors {
+            result += &format!(
+                " <div><span class=\"blob\" style=\"background-color: {}\"></span> {} ({} commits)</div>\n",
+                color,
+                escape_html(author),
+                count
+            );
+        }
+        result += "</div>\n";
+        result
+    }
+}
+
+/// Renders the stylesheet, including a comment describing which
+/// commit counts the shade levels correspond to in each year.
+pub fn css(ext: &ExternalResources, years: &[Year]) -> String {
+    let mut external_css = read_optional_file(ext.external_css.as_ref()).unwrap_or_default();
+    if ext.escape_external_css {
+        external_css = escape_style_end(&external_css);
+    }
+    let theme_css = ext
+        .theme_file
+        .as_ref()
+        .and_then(|path| Theme::from_file(path))
+        .map(|theme| theme.css())
+        .unwrap_or_default();
+    let font_css = ext.embed_font.as_deref().map(font_face).unwrap_or_default();
+    let css = format!(
+        "{}\n{}\n{}\n{}{}{}",
+        CSS,
+        ext.theme.css(),
+        shade_levels_comment(years),
+        font_css,
+        theme_css,
+        external_css
+    );
+    if ext.minify_css {
+        minify_css(&css)
+    } else {
+        css
+    }
+}
+
+/// Returns the css that embeds the font file as base64, and uses it
+/// for all the text.
+fn font_face(path: &Path) -> String {
+    let font = match fs::read(path) {
+        Ok(font) => font,
+        Err(err) => {
+            log::error_println(&format!(
+                "error: could not read the font {}: {}",
+                path.display(),
+                err
+            ));
+            return String::new();
+        }
+    };
+    let extension = path.extension().and_then(OsStr::to_str).unwrap_or("");
+    let (mime_type, format) = match &*extension.to_lowercase() {
+        "woff2" => ("font/woff2", "woff2"),
+        "woff" => ("font/woff", "woff"),
+        "otf" => ("font/otf", "opentype"),
+        _ => ("font/ttf", "truetype"),
+    };
+    format!(
+        "@font-face {{\n    font-family: \"activity-graph-embedded\";\n    src: url(\"data:{};base64,{}\") format(\"{}\");\n}}\n\nhtml, svg {{\n    font-family: \"activity-graph-embedded\", sans-serif;\n}}\n",
+        mime_type,
+        base64::encode(&font),
+        format
+    )
+}
+
+/// Removes the comments and collapses the whitespace of the css,
+/// dropping it entirely around the punctuation that doesn't need it.
+/// Quoted strings are left as they are.
+fn minify_css(css: &str) -> String {
+    let mut result = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+    let mut pending_space = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+                pending_space = true;
+            }
+            c if c.is_whitespace() => pending_space = true,
+            '{' | '}' | ';' | ',' | '>' => {
+                if c == '}' && result.ends_with(';') {
+                    result.pop();
+                }
+                result.push(c);
+                pending_space = false;
+            }
+            _ => {
+                let after_punctuation = result.ends_with(['{', '}', ';', ',', '>']);
+                if pending_space && !result.is_empty() && !after_punctuation {
+                    result.push(' ');
+                }
+                pending_space = false;
+                result.push(c);
+                if c == '"' || c == '\'' {
+                    for quoted in chars.by_ref() {
+                        result.push(quoted);
+                        if quoted == c {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Escapes the "</style" sequences (in any case) as "<\/style", which
+/// css reads the same, but which doesn't end the html <style> element.
+fn escape_style_end(css: &str) -> String {
+    const END_TAG: &str = "</style";
+    let mut result = String::with_capacity(css.len());
+    let mut escaped = 0;
+    let mut rest = css;
+    while let Some(i) = rest.find("</") {
+        result += &rest[..i];
+        let tag = rest.get(i..i + END_TAG.len());
+        if tag.is_some_and(|tag| tag.eq_ignore_ascii_case(END_TAG)) {
+            result += "<\\/";
+            escaped += 1;
+        } else {
+            result += "</";
+        }
+        rest = &rest[i + 2..];
+    }
+    result += rest;
+    if escaped > 0 {
+        log::println(&format!(
+            "warning: escaped {} </style> tags in the external css",
+            escaped
+        ));
+    }
+    result
+}
+
+fn shade_levels_comment(years: &[Year]) -> String {
+    let mut result = String::from("/* Commits per day for each shade level:\n");
+    for year in years.iter().rev() {
+        if let Some(recency) = year.recency {
+            result += &format!(
+                "   {}: weighted by recency, with a half-life of {} days\n",
+                get_label(year),
+                recency.halflife
+            );
+            continue;
+        }
+        let ramp = ShadeRamp::for_year(year);
+        let mut ranges: Vec<Option<(usize, usize)>> = vec![None; ShadeRamp::LEVELS];
+        ranges[0] = Some((0, 0));
+        for count in 1..=ramp.max_count() {
+            let range = &mut ranges[ramp.level(count)];
+            *range = match range {
+                Some((min, _)) => Some((*min, count)),
+                None => Some((count, count)),
+            };
+        }
+
+        // With --clip-at, the darkest shade also covers the days
+        // above the clip
+        let clipped = year
+            .clip_at
+            .is_some_and(|clip_at| year.days.iter().any(|day| year.metric.count(day) > clip_at));
+
+        result += &format!("   {}:", get_label(year));
+        for (level, range) in ranges.iter().enumerate() {
+            result += &match range {
+                Some((min, _)) if clipped && level == ShadeRamp::LEVELS - 1 => {
+                    format!(" .lvl{} = {}+,", level, min)
+                }
+                Some((min, max)) if min == max => format!(" .lvl{} = {},", level, min),
+                Some((min, max)) => format!(" .lvl{} = {}-{},", level, min, max),
+                None => format!(" .lvl{} = unused,", level),
+            };
+        }
+        result.pop();
+        result.push('\n');
+    }
+    result += "*/\n";
+    result
+}
+
+/// Determines whether the ascii output is colored with ANSI escapes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color if stdout is a terminal, and NO_COLOR isn't set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                !no_color && io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ColorChoice, String> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice: {}", s)),
+        }
+    }
+}
+
+/// Renders an ASCII visualization of the commits. If `color` is set,
+/// the days with commits are colored with the palette's ANSI
+/// truecolor escapes.
+pub fn ascii(opts: &RenderOptions, years: &[Year], color: bool) -> String {
+    let mut result = String::with_capacity(512);
+    log::verbose_println("rendering ascii visualization...", true);
+    let displayed = displayed_rows(opts, years);
+    if opts.compact_years {
+        // All the years side by side, oldest first
+        let year_rows: Vec<Vec<String>> = (years.iter())
+            .map(|year| ascii_rows(opts, year, &displayed, color))
+            .collect();
+        let rows = year_rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut lines = vec![String::new(); rows + 1];
+        for (year, rows) in years.iter().zip(year_rows) {
+            let width = ascii_row_width(opts, year, &displayed);
+            lines[0] += &format!("{:<width$}", get_label(year), width = width + 1);
+            for (i, line) in lines[1..].iter_mut().enumerate() {
+                // The vertical grids can have different amounts of
+                // month gaps, so the shorter ones are padded
+                match rows.get(i) {
+                    Some(row) => *line += row,
+                    None => *line += &" ".repeat(width),
+                }
+                line.push(' ');
+            }
+        }
+        result.push('\n');
+        for line in lines {
+            result += line.trim_end();
+            result.push('\n');
+        }
+    } else {
+        for year in years.iter().rev() {
+            let streaks = year.weekly_streaks.map_or(String::new(), |streaks| {
+                format!(
+                    ", {} active weeks, longest streak of {} weeks",
+                    streaks.active_weeks, streaks.longest_streak
+                )
+            });
+            let gap = year.longest_gap.map_or(String::new(), |gap| {
+                format!(
+                    ", longest gap of {} days from {} to {}",
+                    gap.days, gap.start, gap.end
+                )
+            });
+            result += &format!(
+                "\n{} ({} commits{}{})\n",
+                get_label(year),
+                get_total_count(year),
+                streaks,
+                gap
+            );
+            for row in ascii_rows(opts, year, &displayed, color) {
+                result += &row;
+                result.push('\n');
+            }
+        }
+    }
+    log::verbose_println("rendered ascii visualization", false);
+    result
+}
+
+fn ascii_rows(opts: &RenderOptions, year: &Year, rows: &[usize], color: bool) -> Vec<String> {
+    let ramp = ShadeRamp::for_year(year);
+    let cell = |index: usize| -> String {
+        let metadata = &year.days[index];
+        let level = ramp.day_level(year, index);
+        if metadata.filler && opts.filler_style != FillerStyle::SameAsEmpty {
+            String::from(" ")
+        } else if metadata.filler {
+            get_shaded_char(0).to_string()
+        } else if color && level > 0 {
+            let (r, g, b) = SHADE_COLORS[level];
+            format!(
+                "\x1b[38;2;{};{};{}m{}\x1b[0m",
+                r,
+                g,
+                b,
+                get_shaded_char(level)
+            )
+        } else {
+            get_shaded_char(level).to_string()
+        }
+    };
+    match opts.orientation {
+        Orientation::Horizontal => (rows.iter())
+            .map(|&day| {
+                let mut row = String::with_capacity(year.weeks + 12);
+                for week in 0..year.weeks {
+                    if opts.month_gaps && starts_month(year, week) {
+                        row.push('|');
+                    }
+                    row += &cell(day * year.weeks + week);
+                }
+                row
+            })
+            .collect(),
+        Orientation::Vertical => {
+            let mut lines = Vec::with_capacity(year.weeks + 12);
+            for week in 0..year.weeks {
+                if opts.month_gaps && starts_month(year, week) {
+                    lines.push("-".repeat(rows.len()));
+                }
+                lines.push(
+                    (rows.iter())
+                        .map(|&day| cell(day * year.weeks + week))
+                        .collect(),
+                );
+            }
+            lines
+        }
+    }
+}
+
+/// Returns the amount of characters in each of the year's ascii rows,
+/// not counting the color escapes.
+fn ascii_row_width(opts: &RenderOptions, year: &Year, rows: &[usize]) -> usize {
+    if opts.orientation == Orientation::Vertical {
+        return rows.len();
+    }
+    let gaps = if opts.month_gaps {
+        (0..year.weeks)
+            .filter(|week| starts_month(year, *week))
+            .count()
+    } else {
+        0
+    };
+    year.weeks + gaps
+}
+
+/// Returns true if the first day of one of the grid's months falls in
+/// the week, other than the first week of the grid.
+fn starts_month(year: &Year, week: usize) -> bool {
+    let last_day_of_week = |week: usize| year.first_day + Duration::days(week as i64 * 7 + 6);
+    week > 0
+        && year.granularity != Granularity::Quarter
+        && last_day_of_week(week).year() <= year.end_year as i32
+        && last_day_of_week(week).month() != last_day_of_week(week - 1).month()
+}
+
+pub fn create_web_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(s) => s.to_str(),
+            Component::CurDir => Some("."),
+            Component::ParentDir => Some(".."),
+            _ => None,
+        })
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+/// Returns the tooltip describing the day, listing the projects with
+/// the most commits on it, up to `max_projects` of them.
+fn get_tooltip(metric: Metric, day: &Day, max_projects: usize) -> String {
+    let count = metric.count(day);
+    let mut tooltip = match (metric, count) {
+        (Metric::Commits, 0) => String::from("No commits"),
+        (Metric::Commits, _) => format!("{} commits", count),
+        (Metric::ActiveRepos, 0) => String::from("No repos active"),
+        (Metric::ActiveRepos, _) => format!("{} repos active", count),
+    };
+    let mut projects: HashMap<&str, usize> = HashMap::new();
+    for commit in &day.commits {
+        *projects.entry(&commit.project.name).or_insert(0) += 1;
+    }
+    let mut projects: Vec<(&str, usize)> = projects.into_iter().collect();
+    projects.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then(a_name.cmp(b_name)));
+    for (project, commits) in projects.iter().take(max_projects) {
+        tooltip += &match metric {
+            Metric::Commits => format!("\n{}: {}", escape_html(project), commits),
+            Metric::ActiveRepos => format!("\n{}", escape_html(project)),
+        };
+    }
+    if projects.len() > max_projects {
+        tooltip += &format!("\nand {} more", projects.len() - max_projects);
+    }
+    match day.started_projects {
+        0 => {}
+        1 => tooltip += "\nstarted 1 project",
+        started => tooltip += &format!("\nstarted {} projects", started),
+    }
+    for annotation in &day.annotations {
+        tooltip += &format!("\n{}", escape_html(annotation));
+    }
+    tooltip
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn read_optional_file(path: Option<&PathBuf>) -> Option<String> {
+    let path = path?;
+    let file = File::open(path).ok()?;
+    let mut reader = BufReader::new(file);
+    let mut result = Vec::new();
+    reader.read_to_end(&mut result).ok()?;
+    String::from_utf8(result).ok()
+}
+
+/// The names of the commit size buckets, and the amount of changed
+/// lines the commits in each are under. The last one has no limit.
+pub static COMMIT_SIZE_BUCKETS: [(&str, Option<usize>); 4] = [
+    ("tiny", Some(10)),
+    ("small", Some(100)),
+    ("medium", Some(1000)),
+    ("large", None),
+];
+
+/// The amount of weeks with commits in a year, and the longest run of
+/// them. The weeks are ISO weeks, from monday to sunday.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WeeklyStreaks {
+    pub active_weeks: usize,
+    pub longest_streak: usize,
+}
+
+impl WeeklyStreaks {
+    pub fn for_year(year: &Year) -> WeeklyStreaks {
+        let mondays: BTreeSet<NaiveDate> = (0..year.days.len())
+            .filter(|&i| !year.days[i].filler && !year.days[i].commits.is_empty())
+            .map(|i| cell_date(year, i))
+            .map(|date| date - Duration::days(i64::from(date.weekday().num_days_from_monday())))
+            .collect();
+        let mut longest_streak = 0;
+        let mut streak = 0;
+        let mut previous: Option<NaiveDate> = None;
+        for &monday in &mondays {
+            let continues = previous.is_some_and(|previous| previous + Duration::days(7) == monday);
+            streak = if continues { streak + 1 } else { 1 };
+            longest_streak = longest_streak.max(streak);
+            previous = Some(monday);
+        }
+        WeeklyStreaks {
+            active_weeks: mondays.len(),
+            longest_streak,
+        }
+    }
+}
+
+/// The longest run of days without commits in a year, from `start` to
+/// `end`, inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LongestGap {
+    pub days: usize,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+impl LongestGap {
+    /// Finds the longest gap in the year, or returns None if every day
+    /// had commits. Only the year's own days up to today are counted,
+    /// not the filler days from the adjacent years, or the days that
+    /// haven't happened yet.
+    pub fn for_year(year: &Year) -> Option<LongestGap> {
+        let today = Local::today().naive_local();
+        let mut days: Vec<(NaiveDate, bool)> = (0..year.days.len())
+            .filter(|&i| !year.days[i].filler)
+            .map(|i| (cell_date(year, i), year.days[i].commits.is_empty()))
+            .filter(|&(date, _)| date <= today)
+            .collect();
+        days.sort_unstable();
+
+        let mut longest: Option<LongestGap> = None;
+        let mut current: Option<LongestGap> = None;
+        for (date, inactive) in days {
+            if !inactive {
+                current = None;
+                continue;
+            }
+            let gap = match current {
+                Some(gap) => LongestGap {
+                    days: gap.days + 1,
+                    end: date,
+                    ..gap
+                },
+                None => LongestGap {
+                    days: 1,
+                    start: date,
+                    end: date,
+                },
+            };
+            if longest.is_none_or(|longest| gap.days > longest.days) {
+                longest = Some(gap);
+            }
+            current = Some(gap);
+        }
+        longest
+    }
+}
+
+/// Counts the commits in each of the COMMIT_SIZE_BUCKETS, or returns
+/// None if the commit sizes weren't read.
+pub fn commit_sizes(years: &[Year]) -> Option<[usize; 4]> {
+    let mut sizes = [0; 4];
+    let mut any_sized = false;
+    let commits = (years.iter())
+        .flat_map(|year| year.days.iter().filter(|day| !day.filler))
+        .flat_map(|day| &day.commits);
+    for lines_changed in commits.filter_map(|commit| commit.lines_changed) {
+        let bucket = COMMIT_SIZE_BUCKETS
+            .iter()
+            .position(|(_, limit)| limit.is_none_or(|limit| lines_changed < limit))
+            .unwrap_or(COMMIT_SIZE_BUCKETS.len() - 1);
+        sizes[bucket] += 1;
+        any_sized = true;
+    }
+    Some(sizes).filter(|_| any_sized)
+}
+
+/// Counts the commits made during the year. The filler days are
+/// skipped, as their commits are duplicates from the adjacent years.
+pub fn get_total_count(year: &Year) -> usize {
+    year.days
+        .iter()
+        .filter(|day| !day.filler)
+        .map(|day| day.commits.len())
+        .sum()
+}
+
+/// Maps the commit counts of a year to shade levels, relative to the
+/// busiest day of the year. All the renderers use the same levels,
+/// and only differ in how the levels are drawn.
+#[derive(Clone, Copy)]
+pub struct ShadeRamp {
+    max_count: usize,
+    /// The recency weighting, if `--recency-halflife` is set.
+    recency: Option<Recency>,
+}
+
+/// The recency weighting of `--recency-halflife`, shared by all the
+/// years of the graph.
+#[derive(Clone, Copy)]
+pub struct Recency {
+    halflife: f64,
+    /// The last day of the graph, which the ages are relative to.
+    last_day: NaiveDate,
+    /// The highest weighted commit count of a single day in the graph.
+    max_weight: f64,
+}
+
+impl ShadeRamp {
+    /// The amount of shade levels, including the level for no commits.
+    pub const LEVELS: usize = 5;
+
+    pub fn for_year(year: &Year) -> ShadeRamp {
+        let max_count = year
+            .days
+            .iter()
+            .map(|metadata| year.metric.count(metadata))
+            .max()
+            .unwrap_or(0)
+            .min(year.clip_at.unwrap_or(usize::MAX))
+            .max(1);
+        ShadeRamp {
+            max_count,
+            recency: year.recency,
+        }
+    }
+
+    /// The highest commit count of a single day in the year, or the
+    /// `--clip-at` count if it's lower.
+    pub fn max_count(self) -> usize {
+        self.max_count
+    }
+
+    /// Returns the shade level for the commit count, from 0 for no
+    /// commits to `LEVELS - 1` for the busiest days.
+    pub fn level(self, commits: usize) -> usize {
+        if commits == 0 {
+            return 0;
+        }
+        Self::normalized_level(commits as f64 / self.max_count as f64)
+    }
+
+    /// Returns the shade level of the day at `index` in the year,
+    /// which is `level` of its commit count, unless the commits are
+    /// weighted by recency.
+    pub fn day_level(self, year: &Year, index: usize) -> usize {
+        let commits = year.metric.count(&year.days[index]);
+        match self.recency {
+            Some(recency) if commits > 0 && recency.max_weight > 0.0 => {
+                Self::normalized_level(recency.weight(year, index) / recency.max_weight)
+            }
+            _ => self.level(commits),
+        }
+    }
+
+    fn normalized_level(norm: f64) -> usize {
+        let level = 1 + (norm * (Self::LEVELS - 1) as f64) as usize;
+        level.min(Self::LEVELS - 1)
+    }
+}
+
+impl Recency {
+    /// Sets up the weighting for the years, so that the commits fade
+    /// by their age relative to the last day of the whole graph, and
+    /// the days of all the years are shaded on the same scale.
+    fn for_years(years: &[Year], halflife: f64) -> Option<Recency> {
+        let cells = || {
+            years.iter().flat_map(|year| {
+                (0..year.days.len())
+                    .filter(move |&i| !year.days[i].filler)
+                    .map(move |i| (year, i))
+            })
+        };
+        let last_day = cells().map(|(year, i)| cell_date(year, i)).max()?;
+        let mut recency = Recency {
+            halflife,
+            last_day,
+            max_weight: 0.0,
+        };
+        recency.max_weight = cells()
+            .map(|(year, i)| recency.weight(year, i))
+            .fold(0.0, f64::max);
+        Some(recency)
+    }
+
+    /// The commit count of the day (capped by `--clip-at`), halved for
+    /// every `halflife` days between it and the last day.
+    fn weight(self, year: &Year, index: usize) -> f64 {
+        let age = (self.last_day - cell_date(year, index)).num_days() as f64;
+        let commits = year.metric.count(&year.days[index]);
+        let commits = commits.min(year.clip_at.unwrap_or(usize::MAX));
+        commits as f64 * 0.5f64.powf(age / self.halflife)
+    }
+}
+
+fn get_shaded_char(level: usize) -> char {
+    ['\u{2591}', '\u{2592}', '\u{2592}', '\u{2593}', '\u{2593}'][level]
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, TimeZone};
+
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        aggregate_quarters, aggregate_weeks, animation_delay, cell_date, commit_sizes, date_range,
+        displayed_rows, dynamic_title, escape_style_end, gather_aligned_years, gather_continuous,
+        gather_years, get_tooltip, get_total_count, group_thousands, inject_html, minify_css,
+        remove_empty_years, weight_by_recency, LongestGap, Metric, ShadeRamp, WeeklyStreaks,
+        YearAccumulator, WEEKS,
+    };
+    use crate::{Commit, Day, ExternalResources, ProjectMetadata, RenderOptions, Year};
+
+    /// Returns a commit to the project `name`, for the tests.
+    pub(crate) fn commit(name: &str) -> Commit {
+        Commit {
+            project: ProjectMetadata {
+                name: String::from(name),
+                path: PathBuf::from(name),
+            },
+            author: String::from("test"),
+            lines_changed: None,
+        }
+    }
+
+    /// Returns a commit at noon (UTC) on each of the dates, for the
+    /// tests. The dates can repeat for multiple commits on a day.
+    pub(crate) fn commits_on(dates: &[(i32, u32, u32)]) -> Vec<(DateTime<FixedOffset>, Commit)> {
+        let utc = FixedOffset::east(0);
+        dates
+            .iter()
+            .map(|&(y, m, d)| (utc.ymd(y, m, d).and_hms(12, 0, 0), commit("test")))
+            .collect()
+    }
+
+    #[test]
+    fn active_repos_only_affect_the_shades() {
+        let mut commits = commits_on(&[(2020, 6, 1), (2020, 6, 1), (2020, 6, 1), (2020, 6, 2)]);
+        commits[3].1 = commit("other");
+        commits.extend(commits_on(&[(2020, 6, 2)]));
+        let mut years = gather_years(commits, false);
+        let year = &mut years[0];
+        year.metric = Metric::ActiveRepos;
+        assert_eq!(get_total_count(year), 5);
+        let index = |day| {
+            let date = NaiveDate::from_ymd(2020, 6, day);
+            (0..year.days.len())
+                .find(|&i| cell_date(year, i) == date)
+                .unwrap()
+        };
+        let (june_1, june_2) = (index(1), index(2));
+        let ramp = ShadeRamp::for_year(year);
+        assert_eq!(ramp.max_count(), 2);
+        assert!(ramp.day_level(year, june_1) < ramp.day_level(year, june_2));
+        let tooltip = get_tooltip(year.metric, &year.days[june_1], 10);
+        assert!(tooltip.starts_with("1 repos active"));
+    }
+
+    #[test]
+    fn commit_sizes_are_bucketed_under_their_limits() {
+        let mut commits = commits_on(&[(2021, 3, 1); 6]);
+        let sizes = [9, 10, 99, 100, 999, 1000];
+        for ((_, commit), &size) in commits.iter_mut().zip(&sizes) {
+            commit.lines_changed = Some(size);
+        }
+        assert_eq!(
+            commit_sizes(&gather_years(commits, true)),
+            Some([1, 2, 2, 1])
+        );
+        let unsized_years = gather_years(commits_on(&[(2021, 3, 1)]), true);
+        assert_eq!(commit_sizes(&unsized_years), None);
+    }
+
+    #[test]
+    fn filler_commits_dont_keep_weekdays_displayed() {
+        let opts = RenderOptions {
+            hide_empty_weekdays: true,
+            ..RenderOptions::default()
+        };
+        // A thursday, which 2021's grid has as a filler day, and a
+        // tuesday in 2021
+        let years = gather_years(commits_on(&[(2020, 12, 31), (2021, 6, 1)]), true);
+        assert_eq!(displayed_rows(&opts, &years[..1]), vec![3]);
+        assert_eq!(displayed_rows(&opts, &years[1..]), vec![1]);
+    }
+
+    #[test]
+    fn weeks_leave_out_the_filler_days() {
+        // 2020-12-31 is in the first week of 2021's grid, as a filler day
+        let mut years = gather_years(commits_on(&[(2020, 12, 31), (2021, 6, 1)]), true);
+        let filler = (0..years[1].days.len())
+            .find(|&i| cell_date(&years[1], i) == NaiveDate::from_ymd(2020, 12, 31))
+            .unwrap();
+        years[1].days[filler]
+            .annotations
+            .push(String::from("release"));
+        years[1].days[filler].started_projects = 1;
+        aggregate_weeks(&mut years);
+        let first_week = &years[1].days[0];
+        assert!(first_week.commits.is_empty());
+        assert!(first_week.annotations.is_empty());
+        assert_eq!(first_week.started_projects, 0);
+    }
+
+    #[test]
+    fn years_with_only_filler_commits_are_removed() {
+        let mut years = gather_years(commits_on(&[(2018, 12, 31), (2020, 6, 1)]), true);
+        assert_eq!(years.len(), 3);
+        remove_empty_years(&mut years);
+        let kept: Vec<usize> = years.iter().map(|year| year.year).collect();
+        assert_eq!(kept, vec![2018, 2020]);
+    }
+
+    #[test]
+    fn filler_days_only_contain_duplicated_commits() {
+        let dates = [
+            (2012, 12, 31),
+            (2018, 1, 1),
+            (2018, 12, 31),
+            (2019, 1, 1),
+            (2019, 6, 15),
+            (2019, 12, 30),
+            (2019, 12, 31),
+            (2020, 1, 1),
+            (2020, 1, 5),
+            (2020, 12, 31),
+        ];
+        let years = gather_years(commits_on(&dates), true);
+        for (i, year) in years.iter().enumerate() {
+            let real_commits: usize = year
+                .days
+                .iter()
+                .filter(|day| !day.filler)
+                .map(|day| day.commits.len())
+                .sum();
+            let expected = dates.iter().filter(|d| d.0 as usize == year.year).count();
+            assert_eq!(real_commits, expected, "commits hidden in {}", year.year);
+
+            // Every filler day should mirror the same date in the adjacent year
+            for (index, day) in year.days.iter().enumerate() {
+                if !day.filler {
+                    continue;
+                }
+                let date = cell_date(year, index);
+                let adjacent = if index % year.weeks == 0 {
+                    i.checked_sub(1).and_then(|i| years.get(i))
+                } else {
+                    years.get(i + 1)
+                };
+                if let Some(adjacent) = adjacent {
+                    let adjacent_day = (0..adjacent.days.len())
+                        .find(|&j| cell_date(adjacent, j) == date && !adjacent.days[j].filler)
+                        .map(|j| &adjacent.days[j]);
+                    let expected = adjacent_day.map_or(0, |day| day.commits.len());
+                    assert_eq!(day.commits.len(), expected, "mismatch on {}", date);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn batches_are_gathered_like_a_single_batch() {
+        let dates = [(2018, 12, 31), (2019, 1, 1), (2019, 12, 30), (2021, 1, 1)];
+        let mut accumulator = YearAccumulator::new(true);
+        accumulator.add(commits_on(&dates[2..]));
+        accumulator.add(commits_on(&dates[..2]));
+        let counts = |years: &[super::Year]| -> Vec<Vec<usize>> {
+            (years.iter())
+                .map(|year| year.days.iter().map(|day| day.commits.len()).collect())
+                .collect()
+        };
+        assert_eq!(
+            counts(&accumulator.finish()),
+            counts(&gather_years(commits_on(&dates), true))
+        );
+    }
+
+    #[test]
+    fn boundary_days_can_be_left_unduplicated() {
+        let mut accumulator = YearAccumulator::new(false);
+        accumulator.add(commits_on(&[(2018, 12, 31), (2019, 1, 1)]));
+        let commits: Vec<usize> = (accumulator.finish().iter())
+            .map(|year| year.days.iter().map(|day| day.commits.len()).sum())
+            .collect();
+        assert_eq!(commits, vec![1, 1]);
+    }
+
+    #[test]
+    fn last_day_of_leap_year_starting_on_sunday_is_not_lost() {
+        // 2012 starts on a sunday, so its last day is in a 54th week
+        let years = gather_years(commits_on(&[(2012, 6, 1), (2012, 12, 31)]), true);
+        assert_eq!((years[0].year, years[0].weeks), (2012, WEEKS + 1));
+        assert_eq!(get_total_count(&years[0]), 2);
+        let index = (0..years[0].days.len())
+            .find(|&i| cell_date(&years[0], i) == NaiveDate::from_ymd(2012, 12, 31))
+            .unwrap();
+        assert!(!years[0].days[index].filler);
+    }
+
+    #[test]
+    fn days_are_in_the_commits_own_timezone() {
+        let mut commits = commits_on(&[(2019, 6, 1)]);
+        let new_years_eve = FixedOffset::west(5 * 3600)
+            .ymd(2019, 12, 31)
+            .and_hms(23, 30, 0);
+        commits.push((new_years_eve, commits[0].1.clone()));
+        let years = gather_years(commits, true);
+        assert_eq!(years.len(), 1);
+        let index = (0..years[0].days.len())
+            .find(|&i| cell_date(&years[0], i) == NaiveDate::from_ymd(2019, 12, 31))
+            .unwrap();
+        assert_eq!(years[0].days[index].commits.len(), 1);
+    }
+
+    #[test]
+    fn aligned_years_put_the_same_dates_in_the_same_columns() {
+        let years = gather_aligned_years(commits_on(&[(2019, 3, 1), (2021, 3, 1)]));
+        let column = |year: &super::Year| {
+            let index = year.days.iter().position(|day| !day.commits.is_empty());
+            index.unwrap() % WEEKS
+        };
+        assert_eq!(years.len(), 3);
+        assert_eq!(column(&years[0]), column(&years[2]));
+        assert_eq!(cell_date(&years[1], 0), NaiveDate::from_ymd(2020, 1, 1));
+    }
+
+    #[test]
+    fn continuous_grid_counts_each_commit_once() {
+        let dates = [(2018, 12, 31), (2019, 1, 1), (2019, 12, 31), (2020, 1, 1)];
+        let years = gather_continuous(commits_on(&dates));
+        assert_eq!(years.len(), 1);
+        let grid = &years[0];
+        assert_eq!(get_total_count(grid), dates.len());
+        let first = (0..grid.days.len())
+            .find(|&i| !grid.days[i].filler)
+            .unwrap();
+        assert_eq!(cell_date(grid, first), NaiveDate::from_ymd(2018, 12, 31));
+        assert_eq!((grid.year, grid.end_year), (2018, 2020));
+    }
+
+    #[test]
+    fn tooltip_project_list_is_capped() {
+        let mut day = Day::default();
+        for name in &["a", "b", "b", "c"] {
+            let mut commit = commits_on(&[(2020, 1, 1)]).remove(0).1;
+            commit.project.name = name.to_string();
+            day.commits.push(commit);
+        }
+        let tooltip = get_tooltip(Metric::Commits, &day, 2);
+        assert_eq!(tooltip, "4 commits\nb: 2\na: 1\nand 1 more");
+    }
+
+    #[test]
+    fn thousands_are_grouped() {
+        let grouped: Vec<String> = [0, 999, 1000, 1_234_567]
+            .iter()
+            .map(|&n| group_thousands(n))
+            .collect();
+        assert_eq!(grouped, vec!["0", "999", "1,000", "1,234,567"]);
+    }
+
+    #[test]
+    fn minified_css_keeps_the_rules() {
+        let css = "/* Comment */\n.a > .b,\n.c {\n  content: \"  x  \";\n  margin: 0 1px;\n}\n";
+        assert_eq!(
+            minify_css(css),
+            ".a>.b,.c{content: \"  x  \";margin: 0 1px}"
+        );
+    }
+
+    #[test]
+    fn style_end_tags_are_escaped() {
+        let css = ".a { content: \"</STYLE><script>\"; }\n/* </b> */";
+        assert_eq!(
+            escape_style_end(css),
+            ".a { content: \"<\\/STYLE><script>\"; }\n/* </b> */"
+        );
+    }
+
+    #[test]
+    fn injection_replaces_the_marked_region() {
+        let (ext, opts) = (ExternalResources::default(), RenderOptions::default());
+        let years = gather_years(commits_on(&[(2021, 3, 1)]), true);
+        let document = "<p>a</p>\n<!-- activity-graph:start -->old<!-- activity-graph:end -->\n";
+        let inject = |document: &str| {
+            inject_html(&ext, &opts, document, Path::new("index.html"), None, &years)
+        };
+        let injected = inject(document).unwrap();
+        assert!(injected.starts_with("<p>a</p>\n<!-- activity-graph:start -->\n<style>"));
+        assert!(injected.ends_with("</div>\n<!-- activity-graph:end -->\n"));
+        assert!(!injected.contains("old"));
+        assert_eq!(inject(&injected).unwrap(), injected);
+        assert!(inject("<!-- activity-graph:end --><!-- activity-graph:start -->").is_err());
+    }
+
+    #[test]
+    fn shade_levels_are_relative_to_the_busiest_day() {
+        let ramp = ShadeRamp {
+            max_count: 8,
+            recency: None,
+        };
+        let levels: Vec<usize> = (0..=8).map(|count| ramp.level(count)).collect();
+        assert_eq!(levels, vec![0, 1, 2, 2, 3, 3, 4, 4, 4]);
+    }
+
+    #[test]
+    fn recent_commits_are_weighted_more_heavily() {
+        let level_on = |years: &[Year], date: NaiveDate| {
+            let year = years.iter().find(|year| year.year == date.year() as usize);
+            let year = year.unwrap();
+            let index = (0..year.days.len())
+                .find(|&i| !year.days[i].filler && cell_date(year, i) == date)
+                .unwrap();
+            ShadeRamp::for_year(year).day_level(year, index)
+        };
+        let with_recency = |dates: &[(i32, u32, u32)]| {
+            let mut years = gather_years(commits_on(dates), true);
+            weight_by_recency(&mut years, 30.0);
+            years
+        };
+
+        let years = with_recency(&[(2021, 1, 4), (2021, 12, 6), (2021, 12, 27)]);
+        let levels: Vec<usize> = [(1, 4), (12, 6), (12, 27), (12, 28)]
+            .iter()
+            .map(|&(m, d)| level_on(&years, NaiveDate::from_ymd(2021, m, d)))
+            .collect();
+        assert_eq!(levels, vec![1, 3, 4, 0]);
+
+        // The old years fade relative to the end of the whole graph,
+        // even if their days were the busiest
+        let mut dates = vec![(2019, 12, 2); 4];
+        dates.push((2024, 12, 2));
+        let years = with_recency(&dates);
+        assert_eq!(level_on(&years, NaiveDate::from_ymd(2019, 12, 2)), 1);
+        assert_eq!(level_on(&years, NaiveDate::from_ymd(2024, 12, 2)), 4);
+    }
+
+    #[test]
+    fn quarters_contain_their_months_commits() {
+        let commits = commits_on(&[(2020, 3, 31), (2020, 4, 1), (2020, 12, 31), (2021, 1, 1)]);
+        let mut years = gather_years(commits.clone(), true);
+        aggregate_quarters(&mut years);
+        let counts: Vec<usize> = (years[0].days.iter())
+            .map(|quarter| quarter.commits.len())
+            .collect();
+        assert_eq!(counts, vec![1, 1, 0, 1]);
+        assert_eq!(cell_date(&years[0], 1), NaiveDate::from_ymd(2020, 4, 1));
+
+        let mut continuous = gather_continuous(commits);
+        aggregate_quarters(&mut continuous);
+        assert_eq!(continuous[0].days.len(), 8);
+        assert_eq!(continuous[0].days[4].commits.len(), 1);
+        assert!(continuous[0].days[5].filler);
+    }
+
+    #[test]
+    fn weekly_streaks_count_iso_weeks() {
+        // Two consecutive weeks (the second one only on its sunday),
+        // then a gap, then one more week
+        let commits = commits_on(&[(2021, 3, 1), (2021, 3, 3), (2021, 3, 14), (2021, 3, 29)]);
+        let year = gather_years(commits, true).remove(0);
+        assert_eq!(
+            WeeklyStreaks::for_year(&year),
+            WeeklyStreaks {
+                active_weeks: 3,
+                longest_streak: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn longest_gap_skips_the_filler_days() {
+        let commits = commits_on(&[(2020, 1, 10), (2020, 12, 20)]);
+        let year = gather_years(commits, true).remove(0);
+        assert_eq!(
+            LongestGap::for_year(&year),
+            Some(LongestGap {
+                days: 344,
+                start: NaiveDate::from_ymd(2020, 1, 11),
+                end: NaiveDate::from_ymd(2020, 12, 19),
+            })
+        );
+    }
+
+    #[test]
+    fn outliers_are_clipped() {
+        let mut dates = vec![(2021, 3, 1), (2021, 3, 2), (2021, 3, 2)];
+        dates.extend(vec![(2021, 3, 3); 100]);
+        let mut year = gather_years(commits_on(&dates), true).remove(0);
+        year.clip_at = Some(2);
+        let ramp = ShadeRamp::for_year(&year);
+        assert_eq!(ramp.max_count(), 2);
+        assert_eq!((ramp.level(1), ramp.level(2), ramp.level(100)), (3, 4, 4));
+    }
+
+    #[test]
+    fn dynamic_title_has_the_total_and_the_years() {
+        let mut dates = vec![(2018, 3, 1); 1233];
+        dates.push((2024, 5, 1));
+        assert_eq!(
+            dynamic_title(&gather_years(commits_on(&dates), true)),
+            "1,234 commits · 2018–2024"
+        );
+        let years = gather_years(commits_on(&[(2024, 5, 1), (2024, 5, 2)]), true);
+        assert_eq!(dynamic_title(&years), "2 commits · 2024");
+        assert_eq!(dynamic_title(&[]), "0 commits");
+    }
+
+    #[test]
+    fn animation_delays_follow_the_dates() {
+        let years = gather_years(commits_on(&[(2021, 3, 1), (2022, 3, 1)]), true);
+        let (first, last) = date_range(&years).unwrap();
+        assert_eq!(first, NaiveDate::from_ymd(2021, 1, 1));
+        assert_eq!(last, NaiveDate::from_ymd(2022, 12, 31));
+        assert!(animation_delay(first, first, last).abs() < 0.01);
+        assert!((animation_delay(last, first, last) - 4.0).abs() < 0.01);
+        let middle = animation_delay(NaiveDate::from_ymd(2021, 12, 31), first, last);
+        assert!(1.9 < middle && middle < 2.0);
     }
 }
@@ -1,73 +1,275 @@
-use hyper::header::{HeaderValue, CONTENT_TYPE};
+use chrono::{DateTime, Local, NaiveDate};
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Response, Server, StatusCode};
+use hyper::{Body, Client, Method, Request, Response, Server, StatusCode, Uri};
+use serde::Serialize;
 use tokio::runtime::Runtime;
+use tokio::sync::watch;
 use tokio::task;
 
 use std::convert::Infallible;
-use std::fs::File;
+use std::env;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::future::Future;
 use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::RwLock;
-use std::time::{Duration, Instant};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::{generate_years, log, render, ExternalResources, GenerationData};
+use crate::{
+    generate_years, json, log, render, ExternalResources, GenerationData, RenderOptions,
+    ServerOptions, Year,
+};
 
 lazy_static::lazy_static! {
     // These are set before the server is run, and only used in responses
     static ref GENERATION_DATA: RwLock<GenerationData> = RwLock::new(GenerationData::default());
     static ref EXTERNAL_HTML: RwLock<ExternalResources> = RwLock::new(ExternalResources::default());
-    static ref LAST_CACHE: RwLock<Instant> = RwLock::new(Instant::now());
+    static ref RENDER_OPTIONS: RwLock<RenderOptions> = RwLock::new(RenderOptions::default());
     static ref CACHE_LIFETIME: RwLock<Duration> = RwLock::new(Duration::from_secs(0));
+    static ref REFRESH_WEBHOOK: RwLock<Option<Uri>> = RwLock::new(None);
 
-    // A backup of the current CACHED_HTML and CACHED_CSS values on
-    // disk. Encoded in the order: <html> <CACHE_FILE_SPLITTER> <css>
+    // A backup of the cached html and css on disk, along with the
+    // time they were generated at. Encoded in the order: <magic>
+    // <timestamp> <html> <css>, separated by CACHE_FILE_SPLITTER
     static ref CACHE_FILE: RwLock<Option<PathBuf>> = RwLock::new(None);
-    static ref CACHED_HTML: RwLock<String> = RwLock::new(String::new());
-    static ref CACHED_CSS: RwLock<String> = RwLock::new(String::new());
-}
 
-static REFRESHING_CACHE: AtomicBool = AtomicBool::new(false);
-static CACHE_INITIALIZED: AtomicBool = AtomicBool::new(false);
+    static ref CACHE: Cache = Cache::new();
+}
 
 static INDEX_PATHS: &[&str] = &["/", "/index.html", "/index.htm", ""];
+static REFRESH_PATH: &str = "/refresh";
+static CSS_PATH: &str = "/activity-graph.css";
+static DAY_PATH_PREFIX: &str = "/day/";
+static HEALTH_PATH: &str = "/healthz";
 
 // This is invalid UTF-8, and so can be used as a delimiter between
 // Strings, as Strings are always valid UTF-8.
 const CACHE_FILE_SPLITTER: u8 = 0xFE;
+const CACHE_FILE_MAGIC: &str = "ACTIVITY-GRAPH-CACHE-FILE-2";
+// The cache files written before the timestamp was added
+const CACHE_FILE_MAGIC_V1: &str = "ACTIVITY-GRAPH-CACHE-FILE";
+
+/// The generated responses, which are always swapped in together.
+struct CacheContent {
+    html: String,
+    css: String,
+    /// The years the html was generated from, for the day queries.
+    /// None if the html was loaded from the cache file, as the years
+    /// aren't stored in it.
+    years: Option<Vec<Year>>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// None until the cache has been initialized.
+    content: Option<CacheContent>,
+    /// The time of the last cache refresh. None means the cache is
+    /// stale, and will be refreshed on the next request.
+    last_cache: Option<Instant>,
+}
+
+/// The cached responses. The state is only ever replaced as a whole
+/// under the lock, so the requests never see half of a refresh.
+struct Cache {
+    state: RwLock<CacheState>,
+    refreshing: AtomicBool,
+    /// Changes to true when the first content has been swapped in,
+    /// which the early requests wait for.
+    initialized: watch::Sender<bool>,
+    initialized_receiver: watch::Receiver<bool>,
+}
+
+impl Cache {
+    fn new() -> Cache {
+        let (initialized, initialized_receiver) = watch::channel(false);
+        Cache {
+            state: RwLock::new(CacheState::default()),
+            refreshing: AtomicBool::new(false),
+            initialized,
+            initialized_receiver,
+        }
+    }
+
+    fn is_initialized(&self) -> bool {
+        *self.initialized_receiver.borrow()
+    }
+
+    /// Refreshes the cache with `refresh` in a blocking task if it's
+    /// older than `lifetime` and not already being refreshed, and
+    /// waits until the cache has been initialized.
+    async fn refresh(&'static self, lifetime: Duration, refresh: fn(&Cache)) {
+        task::spawn_blocking(move || {
+            let is_stale = || match self.state.read() {
+                Ok(state) => state
+                    .last_cache
+                    .is_none_or(|last_cache| last_cache.elapsed() >= lifetime),
+                Err(_) => false,
+            };
+            if is_stale()
+                && self
+                    .refreshing
+                    .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                let _guard = RefreshGuard(self);
+                // Another refresh may have finished between the first
+                // check and taking the flag
+                if is_stale() {
+                    log::verbose_println("refreshing cache...", false);
+                    refresh(self);
+                }
+            }
+        });
+
+        let mut initialized = self.initialized_receiver.clone();
+        while !*initialized.borrow() {
+            if initialized.recv().await.is_none() {
+                break;
+            }
+        }
+    }
+
+    /// Swaps in the new content, refreshed at `last_cache`.
+    fn swap(&self, content: CacheContent, last_cache: Option<Instant>) {
+        if let Ok(mut state) = self.state.write() {
+            *state = CacheState {
+                content: Some(content),
+                last_cache,
+            };
+        }
+        let _ = self.initialized.broadcast(true);
+    }
+
+    /// Marks the cache as stale.
+    fn invalidate(&self) {
+        if let Ok(mut state) = self.state.write() {
+            state.last_cache = None;
+        }
+    }
+}
+
+/// Allows future refreshes when dropped, and wakes up the requests
+/// waiting for the cache, even if the refresh failed or panicked, so
+/// that they don't wait forever.
+struct RefreshGuard<'a>(&'a Cache);
+
+impl Drop for RefreshGuard<'_> {
+    fn drop(&mut self) {
+        self.0.refreshing.store(false, Ordering::Release);
+        let _ = self.0.initialized.broadcast(true);
+    }
+}
+
+/// Everything the request handler depends on. `run` wires the global
+/// cache into it, the tests construct their own.
+struct Context {
+    /// The path the routes are under, without a trailing slash.
+    base_path: String,
+    refresh_token: Option<String>,
+    cache: &'static Cache,
+    /// Refreshes the caches if they're stale, and waits until they
+    /// have been initialized.
+    refresh: fn() -> Pin<Box<dyn Future<Output = ()> + Send>>,
+    /// Marks the caches as stale, and starts refreshing them in the
+    /// background.
+    invalidate: fn(),
+}
 
 pub fn run(
-    gen: &GenerationData,
+    mut gen: GenerationData,
     ext: &ExternalResources,
-    cache_file: Option<PathBuf>,
-    host: SocketAddr,
-    cache_lifetime: u64,
+    opts: &RenderOptions,
+    server_opts: ServerOptions,
 ) {
+    // Fall back to the environment variables for the arguments that
+    // weren't given, then to the defaults
+    if gen.input.is_empty() {
+        if let Some(input) = env::var_os("ACTIVITY_GRAPH_INPUT") {
+            gen.input = env::split_paths(&input).collect();
+        }
+    }
+    if gen.author.is_none() {
+        gen.author = env_var("ACTIVITY_GRAPH_AUTHOR");
+    }
+    let host = (server_opts.host)
+        .or_else(|| env_var("ACTIVITY_GRAPH_HOST"))
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 80)));
+    let cache_lifetime = (server_opts.cache_lifetime)
+        .or_else(|| env_var("ACTIVITY_GRAPH_CACHE_LIFETIME"))
+        .unwrap_or(1);
+    let base_path = server_opts.base_path.unwrap_or_default();
+    let base_path = format!("/{}", base_path.trim_matches('/'));
+    let base_path = base_path.trim_end_matches('/');
+    // Exiting on a bad refresh would take the server down with it
+    if gen.strict {
+        log::println("warning: --strict is ignored by the server");
+        gen.strict = false;
+    }
+    let cache_file = if server_opts.cache_file_auto {
+        default_cache_file()
+    } else {
+        server_opts.cache_file
+    };
+    // The client only speaks plain http, there's no tls connector
+    let webhook = server_opts.on_refresh_webhook.filter(|uri| {
+        if uri.scheme_str() == Some("http") {
+            true
+        } else {
+            log::error_println("error: --on-refresh-webhook must be an http:// url, ignoring it");
+            false
+        }
+    });
+
     log::verbose_println(&format!("starting server on {}...", host), true);
 
-    if let (Ok(mut gen_), Ok(mut ext_), Ok(mut cache_file_), Ok(mut lifetime), Ok(mut last_cache)) = (
+    if let (
+        Ok(mut gen_),
+        Ok(mut ext_),
+        Ok(mut opts_),
+        Ok(mut cache_file_),
+        Ok(mut lifetime),
+        Ok(mut webhook_),
+    ) = (
         GENERATION_DATA.write(),
         EXTERNAL_HTML.write(),
+        RENDER_OPTIONS.write(),
         CACHE_FILE.write(),
         CACHE_LIFETIME.write(),
-        LAST_CACHE.write(),
+        REFRESH_WEBHOOK.write(),
     ) {
-        *gen_ = gen.clone();
+        *gen_ = gen;
         *ext_ = ext.clone();
+        *opts_ = opts.clone();
         *cache_file_ = cache_file;
         *lifetime = Duration::from_secs(cache_lifetime);
-        *last_cache = Instant::now() - Duration::from_secs(cache_lifetime * 2);
+        *webhook_ = webhook;
     } else {
         unreachable!();
     }
 
+    let context = Arc::new(Context {
+        base_path: base_path.to_string(),
+        refresh_token: server_opts.refresh_token,
+        cache: &CACHE,
+        refresh: || Box::pin(refresh_caches()),
+        invalidate: invalidate_caches,
+    });
+
     match Runtime::new() {
         Ok(mut runtime) => {
             runtime.block_on(async {
-                let make_service =
-                    make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle)) });
+                let make_service = make_service_fn(|_conn| {
+                    let context = context.clone();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |req| handle(context.clone(), req)))
+                    }
+                });
                 let server = Server::bind(&host).serve(make_service);
                 log::println(&format!("server started on {}", host));
                 if let Err(err) = server.await {
@@ -84,19 +286,61 @@ pub fn run(
     }
 }
 
-async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
-    let (cache, mime_type) = if INDEX_PATHS.contains(&req.uri().path()) {
-        refresh_caches().await;
-        (CACHED_HTML.read(), HeaderValue::from_static("text/html"))
-    } else if req.uri() == "/activity-graph.css" {
-        refresh_caches().await;
-        (CACHED_CSS.read(), HeaderValue::from_static("text/css"))
-    } else {
+async fn handle(context: Arc<Context>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let base_path = &context.base_path;
+    let path = req.uri().path();
+    if !base_path.is_empty() && path == base_path {
+        // Redirect to the path with the trailing slash, so that the
+        // relative stylesheet link resolves under the base path
+        let mut response = error_response("308 Permanent Redirect", StatusCode::PERMANENT_REDIRECT);
+        if let Ok(location) = HeaderValue::from_str(&format!("{}/", base_path)) {
+            response.headers_mut().insert(LOCATION, location);
+        }
+        return Ok(response);
+    }
+    let Some(path) = path.strip_prefix(base_path.as_str()) else {
         return Ok(error_response("404 Not Found", StatusCode::NOT_FOUND));
     };
-    if let Ok(cache) = cache {
-        let mut response = Response::new(Body::from(cache.clone()));
+
+    if path == REFRESH_PATH {
+        return Ok(handle_refresh(&context, &req));
+    }
+    if path == HEALTH_PATH {
+        return Ok(handle_health(&context));
+    }
+    if let Some(date) = path.strip_prefix(DAY_PATH_PREFIX) {
+        (context.refresh)().await;
+        return Ok(handle_day(&context, date));
+    }
+
+    let is_css = path == CSS_PATH;
+    if !is_css && !INDEX_PATHS.contains(&path) {
+        return Ok(error_response("404 Not Found", StatusCode::NOT_FOUND));
+    }
+    (context.refresh)().await;
+    let mime_type = HeaderValue::from_static(if is_css { "text/css" } else { "text/html" });
+    // Only hold the read lock for the duration of the clone
+    let cache = context.cache.state.read().ok().and_then(|state| {
+        let cached = state.content.as_ref()?;
+        Some(if is_css {
+            cached.css.clone()
+        } else {
+            cached.html.clone()
+        })
+    });
+    if let Some(cache) = cache {
+        // HEAD responses get the same headers as GET, without the body
+        let content_length = HeaderValue::from(cache.len());
+        let body = if req.method() == Method::HEAD {
+            Body::empty()
+        } else {
+            Body::from(cache)
+        };
+        let mut response = Response::new(body);
         response.headers_mut().insert(CONTENT_TYPE, mime_type);
+        response
+            .headers_mut()
+            .insert(CONTENT_LENGTH, content_length);
         Ok(response)
     } else {
         Ok(error_response(
@@ -106,126 +350,549 @@ async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
     }
 }
 
+/// Returns the cache file under the user's cache directory, creating
+/// the directories leading to it, or None if there's no cache
+/// directory or it can't be created.
+fn default_cache_file() -> Option<PathBuf> {
+    let Some(cache_dir) = dirs::cache_dir() else {
+        log::error_println("error: could not find the cache directory for --cache-file-auto");
+        return None;
+    };
+    let dir = cache_dir.join("activity-graph");
+    if let Err(err) = fs::create_dir_all(&dir) {
+        log::error_println(&format!(
+            "error: could not create {}: {}",
+            dir.display(),
+            err
+        ));
+        return None;
+    }
+    let path = dir.join("cache");
+    log::println(&format!("using {} as the cache file", path.display()));
+    Some(path)
+}
+
+/// Reads and parses the environment variable, if it's set. Invalid
+/// values are reported and ignored.
+fn env_var<T: FromStr>(name: &str) -> Option<T>
+where
+    T::Err: Display,
+{
+    let value = env::var(name).ok()?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(err) => {
+            log::println(&format!("error: invalid value for {}: {}", name, err));
+            None
+        }
+    }
+}
+
+fn handle_refresh(context: &Context, req: &Request<Body>) -> Response<Body> {
+    if req.method() != Method::POST && req.method() != Method::GET {
+        return error_response("405 Method Not Allowed", StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    let authorized = match &context.refresh_token {
+        Some(token) => {
+            let expected_param = format!("token={}", token);
+            let query = req.uri().query().unwrap_or("");
+            query.split('&').any(|param| param == expected_param)
+        }
+        None => true,
+    };
+    if !authorized {
+        return error_response("403 Forbidden", StatusCode::FORBIDDEN);
+    }
+
+    (context.invalidate)();
+    log::verbose_println("cache invalidated via the refresh endpoint", false);
+
+    let mut response = Response::new(Body::from("202 Accepted"));
+    *response.status_mut() = StatusCode::ACCEPTED;
+    response
+}
+
+/// Reports whether the cache has been successfully generated (or
+/// loaded from the cache file) at least once. Unlike the content
+/// routes, this never refreshes the cache, so that frequent health
+/// probes don't cause git scans.
+fn handle_health(context: &Context) -> Response<Body> {
+    let initialized = (context.cache.state.read()).is_ok_and(|state| state.content.is_some());
+    if initialized {
+        Response::new(Body::from("ok"))
+    } else {
+        error_response("503 Service Unavailable", StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+/// Responds with the commits of the day as json, in the format of
+/// the days in the json subcommand's output.
+fn handle_day(context: &Context, date: &str) -> Response<Body> {
+    let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") else {
+        return error_response("400 Bad Request", StatusCode::BAD_REQUEST);
+    };
+    let day_json = match context.cache.state.read() {
+        Ok(state) => match state.content.as_ref().and_then(|c| c.years.as_ref()) {
+            Some(years) => json::render_day(years, date),
+            None => {
+                return error_response("503 Service Unavailable", StatusCode::SERVICE_UNAVAILABLE);
+            }
+        },
+        Err(_) => None,
+    };
+    match day_json {
+        Some(day_json) => {
+            let mut response = Response::new(Body::from(day_json));
+            (response.headers_mut())
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            response
+        }
+        None => error_response("404 Not Found", StatusCode::NOT_FOUND),
+    }
+}
+
 fn error_response(s: &'static str, status_code: StatusCode) -> Response<Body> {
     let mut response = Response::new(Body::from(s));
     *response.status_mut() = status_code;
     response
 }
 
-async fn refresh_caches() {
-    task::spawn_blocking(|| {
-        let refresh_time = {
-            let last_cache = LAST_CACHE.read().unwrap();
-            let lifetime = CACHE_LIFETIME.read().unwrap();
-            *last_cache + *lifetime
-        };
-        if Instant::now() >= refresh_time
-            && !REFRESHING_CACHE.compare_and_swap(false, true, Ordering::Relaxed)
-        {
-            log::verbose_println("refreshing cache...", false);
+fn invalidate_caches() {
+    CACHE.invalidate();
+    task::spawn(refresh_caches());
+}
 
+async fn refresh_caches() {
+    let lifetime = CACHE_LIFETIME.read().map_or(Duration::from_secs(0), |l| *l);
+    CACHE
+        .refresh(lifetime, |cache| {
             // Load from cache file if the cache has not been
             // initialized yet (if it exists)
-            if !CACHE_INITIALIZED.load(Ordering::Relaxed) {
-                if let Some((html, css)) = read_cache_file() {
-                    if let (Ok(mut html_cache), Ok(mut css_cache)) =
-                        (CACHED_HTML.write(), CACHED_CSS.write())
-                    {
-                        *html_cache = html;
-                        *css_cache = css;
-                        CACHE_INITIALIZED.store(true, Ordering::Relaxed);
-                        log::println("initialized cache from cache file");
-                    }
-                }
+            let fresh_from_file = !cache.is_initialized() && load_cache_file(cache);
+            if !fresh_from_file {
+                regenerate_caches(cache);
             }
+        })
+        .await;
+}
 
-            let start = Instant::now();
-            if let (Ok(gen), Ok(ext)) = (GENERATION_DATA.read(), EXTERNAL_HTML.read()) {
-                let years = generate_years(&gen);
-                let html_path = PathBuf::from("/index");
-                let css_path = PathBuf::from("/activity-graph.css");
-                let output_html = render::html(&ext, &html_path, Some(&css_path), &years);
-                let output_css = render::css(&ext);
-
-                let (cache_html, cache_css) = (output_html.clone(), output_css.clone());
-                task::spawn(async move {
-                    if let Err(err) = write_cache_file(&cache_html, &cache_css) {
-                        log::println(&format!(
-                            "error: ran into an IO error while writing cache file: {}",
-                            err
-                        ));
-                    }
-                });
+/// Initializes the cache from the cache file, if there is one.
+/// Returns true if the cached content is recent enough that it
+/// doesn't need to be regenerated yet.
+fn load_cache_file(cache: &Cache) -> bool {
+    let Some((generated_at, html, css)) = read_cache_file() else {
+        return false;
+    };
 
-                if let Ok(mut html) = CACHED_HTML.write() {
-                    *html = output_html;
-                }
-                if let Ok(mut css) = CACHED_CSS.write() {
-                    *css = output_css;
-                }
-                if let Ok(mut last_cache) = LAST_CACHE.write() {
-                    *last_cache = Instant::now();
-                }
-            }
-            log::println(&format!("updated cache, took {:?}", Instant::now() - start));
+    // Without a timestamp, the age of the cache is unknown, so it's
+    // treated as stale
+    let age = generated_at.and_then(|time| SystemTime::now().duration_since(time).ok());
+    let last_cache = age.and_then(|age| Instant::now().checked_sub(age));
+    let years = None;
+    cache.swap(CacheContent { html, css, years }, last_cache);
+    log::println("initialized cache from cache file");
 
-            REFRESHING_CACHE.store(false, Ordering::Relaxed); // Allow future refreshes
-            CACHE_INITIALIZED.store(true, Ordering::Relaxed); // Allow early requests to complete
+    let lifetime = CACHE_LIFETIME.read().map_or(Duration::from_secs(0), |l| *l);
+    age.is_some_and(|age| age < lifetime)
+}
+
+/// Generates the html and css, and swaps them into the cache.
+fn regenerate_caches(cache: &Cache) {
+    let start = Instant::now();
+    // Clone the configuration out of the locks, so that they
+    // aren't held during the (potentially very slow) generation
+    let config = match (
+        GENERATION_DATA.read(),
+        EXTERNAL_HTML.read(),
+        RENDER_OPTIONS.read(),
+    ) {
+        (Ok(gen), Ok(ext), Ok(opts)) => Some((gen.clone(), ext.clone(), opts.clone())),
+        _ => None,
+    };
+    if let Some((gen, ext, opts)) = config {
+        let years = generate_years(&gen);
+        let commits = years.iter().map(render::get_total_count).sum();
+        let html_path = PathBuf::from("/index");
+        let css_path = PathBuf::from(CSS_PATH);
+        let output_html = render::html(&ext, &opts, &html_path, Some(&css_path), &years);
+        let output_css = render::css(&ext, &years);
+        let (cache_html, cache_css) = (output_html.clone(), output_css.clone());
+        let content = CacheContent {
+            html: output_html,
+            css: output_css,
+            years: Some(years),
+        };
+        cache.swap(content, Some(Instant::now()));
+
+        // This is a blocking task already, so the file can be
+        // written here without stalling the request handlers
+        if let Err(err) = write_cache_file(SystemTime::now(), &cache_html, &cache_css) {
+            log::println(&format!(
+                "error: ran into an IO error while writing cache file: {}",
+                err
+            ));
         }
-    });
 
-    // Yield until the cache has been initialized
-    while !CACHE_INITIALIZED.load(Ordering::Relaxed) {
-        task::yield_now().await;
+        let webhook = REFRESH_WEBHOOK.read().ok().and_then(|uri| uri.clone());
+        if let Some(uri) = webhook {
+            let payload = WebhookPayload {
+                commits,
+                duration_ms: start.elapsed().as_millis(),
+            };
+            // Spawned, so that a slow webhook doesn't hold up the
+            // next refreshes
+            task::spawn(call_webhook(uri, payload));
+        }
     }
+    log::println(&format!("updated cache, took {:?}", start.elapsed()));
+}
+
+/// The body of the `--on-refresh-webhook` requests.
+#[derive(Serialize)]
+struct WebhookPayload {
+    /// The total amount of commits in the refreshed years.
+    commits: usize,
+    /// How long the refresh took, in milliseconds.
+    duration_ms: u128,
 }
 
-fn write_cache_file(html: &str, css: &str) -> Result<(), io::Error> {
-    if let Ok(cache_file) = CACHE_FILE.read() {
-        if cache_file.is_some() {
-            log::verbose_println("writing cache file...", true);
-            let file = File::create(cache_file.as_ref().unwrap())?;
-            let mut writer = BufWriter::new(file);
-            write!(writer, "ACTIVITY-GRAPH-CACHE-FILE")?;
-            writer.write(&[CACHE_FILE_SPLITTER])?;
-            write!(writer, "{}", html)?;
-            writer.write(&[CACHE_FILE_SPLITTER])?;
-            write!(writer, "{}", css)?;
-            drop(writer); // This should flush out the file write
-            log::verbose_println("wrote cache file", false);
+/// POSTs the payload to the webhook as json. The failures are only
+/// logged, the cache has already been refreshed at this point.
+async fn call_webhook(uri: Uri, payload: WebhookPayload) {
+    let request = serde_json::to_string(&payload)
+        .map_err(|err| err.to_string())
+        .and_then(|body| {
+            Request::post(uri.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .map_err(|err| err.to_string())
+        });
+    let response = match request {
+        Ok(request) => Client::new()
+            .request(request)
+            .await
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err),
+    };
+    match response {
+        Ok(response) if response.status().is_success() => {
+            log::verbose_println(&format!("called the refresh webhook {}", uri), false);
         }
+        Ok(response) => log::println(&format!(
+            "error: the refresh webhook {} responded with {}",
+            uri,
+            response.status()
+        )),
+        Err(err) => log::println(&format!(
+            "error: could not call the refresh webhook {}: {}",
+            uri, err
+        )),
+    }
+}
+
+fn write_cache_file(generated_at: SystemTime, html: &str, css: &str) -> Result<(), io::Error> {
+    let cache_file = CACHE_FILE.read().ok().and_then(|path| path.clone());
+    if let Some(cache_file) = cache_file {
+        log::verbose_println("writing cache file...", true);
+        let file = File::create(cache_file)?;
+        let mut writer = BufWriter::new(file);
+        let timestamp = generated_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        write!(writer, "{}", CACHE_FILE_MAGIC)?;
+        writer.write_all(&[CACHE_FILE_SPLITTER])?;
+        write!(writer, "{}", timestamp)?;
+        writer.write_all(&[CACHE_FILE_SPLITTER])?;
+        write!(writer, "{}", html)?;
+        writer.write_all(&[CACHE_FILE_SPLITTER])?;
+        write!(writer, "{}", css)?;
+        drop(writer); // This should flush out the file write
+        log::verbose_println("wrote cache file", false);
     }
     Ok(())
 }
 
-fn read_cache_file() -> Option<(String, String)> {
-    if let Ok(cache_file) = CACHE_FILE.read() {
-        if cache_file.is_some() {
-            let file = File::open(cache_file.as_ref().unwrap());
-            match file {
-                Ok(file) => {
-                    let mut reader = BufReader::new(file);
-                    let mut bytes = Vec::new();
-                    if reader.read_to_end(&mut bytes).is_ok() {
-                        // Split at CACHE_FILE_SPLITTER and return the
-                        // parts between as `&str`s.
-                        let parts: Vec<&str> = bytes
-                            .split(|b| *b == CACHE_FILE_SPLITTER)
-                            .filter_map(|bytes: &[u8]| std::str::from_utf8(bytes).ok())
-                            .collect();
-                        if parts.len() == 3 {
-                            let (magic, html, css) = (parts[0], parts[1], parts[2]);
-                            if magic == "ACTIVITY-GRAPH-CACHE-FILE" {
-                                return Some((html.to_string(), css.to_string()));
-                            }
-                        }
-                    }
-                }
-                Err(err) => {
-                    log::println(&format!("error: could not read cache file: {}", err));
+/// The generation time, html and css stored in a cache file. The time
+/// is None for cache files written by older versions.
+type CacheFileContents = (Option<SystemTime>, String, String);
+
+/// Reads the contents of the cache file, if there is one.
+fn read_cache_file() -> Option<CacheFileContents> {
+    let cache_file = CACHE_FILE.read().ok().and_then(|path| path.clone())?;
+    match open_cache_file(&cache_file) {
+        Ok(contents) => Some(contents),
+        Err(err) => {
+            log::println(&format!("error: could not read cache file: {}", err));
+            None
+        }
+    }
+}
+
+fn open_cache_file(path: &Path) -> Result<CacheFileContents, String> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .map(BufReader::new)
+        .and_then(|mut reader| reader.read_to_end(&mut bytes))
+        .map_err(|err| err.to_string())?;
+    parse_cache_file(&bytes)
+}
+
+fn parse_cache_file(bytes: &[u8]) -> Result<CacheFileContents, String> {
+    // Split at CACHE_FILE_SPLITTER and return the parts between as
+    // `&str`s.
+    let parts = bytes
+        .split(|b| *b == CACHE_FILE_SPLITTER)
+        .map(std::str::from_utf8)
+        .collect::<Result<Vec<&str>, _>>()
+        .map_err(|_| String::from("the file is corrupt (invalid UTF-8)"))?;
+    match parts[..] {
+        [CACHE_FILE_MAGIC, timestamp, html, css] => {
+            let generated_at = timestamp
+                .parse()
+                .ok()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+            Ok((generated_at, html.to_string(), css.to_string()))
+        }
+        [CACHE_FILE_MAGIC_V1, html, css] => Ok((None, html.to_string(), css.to_string())),
+        [CACHE_FILE_MAGIC | CACHE_FILE_MAGIC_V1, ..] => Err(format!(
+            "the file is corrupt (found {} sections)",
+            parts.len()
+        )),
+        _ => Err(String::from("not an activity-graph cache file")),
+    }
+}
+
+/// Validates the cache file, and prints out what's stored in it, for
+/// the `cache-info` subcommand.
+pub fn print_cache_info(path: &Path) {
+    match open_cache_file(path) {
+        Ok((generated_at, html, css)) => {
+            println!("cache file: {}", path.display());
+            match generated_at {
+                Some(time) => {
+                    let age = SystemTime::now().duration_since(time).ok();
+                    println!(
+                        "generated at: {} ({} seconds ago)",
+                        DateTime::<Local>::from(time).to_rfc3339(),
+                        age.map_or(0, |age| age.as_secs())
+                    );
                 }
+                None => println!("generated at: unknown (written by an older version)"),
             }
+            println!("html: {} bytes", html.len());
+            println!("css: {} bytes", css.len());
+        }
+        Err(err) => log::error_println(&format!(
+            "error: invalid cache file {}: {}",
+            path.display(),
+            err
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{HeaderName, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+    use hyper::{Body, Method, Request, StatusCode};
+    use tokio::runtime::Runtime;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    use super::{handle, parse_cache_file, Cache, CacheContent, Context};
+    use super::{CACHE_FILE_MAGIC, CACHE_FILE_SPLITTER};
+    use crate::render::{self, tests::commits_on};
+    use crate::Year;
+
+    fn context(base_path: &str, refresh_token: Option<&str>) -> Arc<Context> {
+        let cache = Cache::new();
+        let content = CacheContent {
+            html: String::from("<html>"),
+            css: String::from("html {}"),
+            years: Some(years()),
+        };
+        cache.swap(content, Some(Instant::now()));
+        Arc::new(Context {
+            base_path: base_path.to_string(),
+            refresh_token: refresh_token.map(ToString::to_string),
+            cache: Box::leak(Box::new(cache)),
+            refresh: || Box::pin(async {}),
+            invalidate: || {},
+        })
+    }
+
+    fn years() -> Vec<Year> {
+        render::gather_years(commits_on(&[(2020, 5, 14)]), true)
+    }
+
+    /// Sends the request to the handler, returning the status, the
+    /// given header's value, and the body.
+    fn request(
+        context: Arc<Context>,
+        method: Method,
+        uri: &str,
+        header: HeaderName,
+    ) -> (StatusCode, String, String) {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        let mut runtime = Runtime::new().unwrap();
+        runtime.block_on(async {
+            let response = handle(context, req).await.unwrap();
+            let status = response.status();
+            let header = response.headers().get(header);
+            let header = header
+                .map_or("", |value| value.to_str().unwrap())
+                .to_string();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            (status, header, String::from_utf8(body.to_vec()).unwrap())
+        })
+    }
+
+    #[test]
+    fn serves_the_cached_html_and_css() {
+        let get = |uri| request(context("", None), Method::GET, uri, CONTENT_TYPE);
+        let (status, mime, body) = get("/");
+        assert_eq!(
+            (status, &*mime, &*body),
+            (StatusCode::OK, "text/html", "<html>")
+        );
+        let (status, mime, body) = get("/activity-graph.css");
+        assert_eq!(
+            (status, &*mime, &*body),
+            (StatusCode::OK, "text/css", "html {}")
+        );
+        assert_eq!(get("/missing").0, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn head_requests_get_the_headers_without_the_body() {
+        let (status, length, body) = request(context("", None), Method::HEAD, "/", CONTENT_LENGTH);
+        assert_eq!((status, &*length, &*body), (StatusCode::OK, "6", ""));
+    }
+
+    #[test]
+    fn concurrent_first_requests_wait_for_a_single_generation() {
+        static GENERATIONS: AtomicUsize = AtomicUsize::new(0);
+        lazy_static::lazy_static! {
+            static ref CACHE: Cache = Cache::new();
         }
+        let context = Arc::new(Context {
+            base_path: String::new(),
+            refresh_token: None,
+            cache: &CACHE,
+            refresh: || {
+                Box::pin(CACHE.refresh(Duration::from_secs(100), |cache| {
+                    GENERATIONS.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(100));
+                    let content = CacheContent {
+                        html: String::from("<html>"),
+                        css: String::new(),
+                        years: None,
+                    };
+                    cache.swap(content, Some(Instant::now()));
+                }))
+            },
+            invalidate: || {},
+        });
+
+        let mut runtime = Runtime::new().unwrap();
+        let bodies = runtime.block_on(async {
+            let requests: Vec<_> = (0..8)
+                .map(|_| {
+                    let req = Request::get("/").body(Body::empty()).unwrap();
+                    tokio::spawn(handle(context.clone(), req))
+                })
+                .collect();
+            let mut bodies = Vec::new();
+            for request in requests {
+                let response = request.await.unwrap().unwrap();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                bodies.push(String::from_utf8(body.to_vec()).unwrap());
+            }
+            bodies
+        });
+        assert!(bodies.iter().all(|body| body == "<html>"), "{:?}", bodies);
+        assert_eq!(GENERATIONS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn panicking_refreshes_dont_leave_requests_waiting() {
+        let cache: &'static Cache = Box::leak(Box::new(Cache::new()));
+        let mut runtime = Runtime::new().unwrap();
+        // The flag is cleared before the waiting requests are woken up
+        runtime.block_on(cache.refresh(Duration::from_secs(100), |_| panic!("refresh failed")));
+        assert!(cache.is_initialized());
+        assert!(!cache.refreshing.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn day_queries_return_the_days_commits() {
+        let get = |uri| request(context("", None), Method::GET, uri, CONTENT_TYPE);
+        let (status, mime, body) = get("/day/2020-05-14");
+        assert_eq!((status, &*mime), (StatusCode::OK, "application/json"));
+        assert!(body.contains("\"commits\": 1"), "{}", body);
+        assert_eq!(get("/day/2020-05-15").0, StatusCode::OK);
+        assert_eq!(get("/day/2019-05-14").0, StatusCode::NOT_FOUND);
+        assert_eq!(get("/day/yesterday").0, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn health_checks_dont_refresh_the_cache() {
+        let uninitialized = Arc::new(Context {
+            base_path: String::new(),
+            refresh_token: None,
+            cache: Box::leak(Box::new(Cache::new())),
+            refresh: || panic!("the health check refreshed the cache"),
+            invalidate: || {},
+        });
+        let (status, _, _) = request(uninitialized, Method::GET, "/healthz", CONTENT_TYPE);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, _, body) = request(context("", None), Method::GET, "/healthz", CONTENT_TYPE);
+        assert_eq!((status, &*body), (StatusCode::OK, "ok"));
+    }
+
+    #[test]
+    fn routes_under_the_base_path() {
+        let get = |uri| request(context("/activity", None), Method::GET, uri, LOCATION);
+        let (status, location, _) = get("/activity");
+        assert_eq!(
+            (status, &*location),
+            (StatusCode::PERMANENT_REDIRECT, "/activity/")
+        );
+        let (status, _, body) = get("/activity/");
+        assert_eq!((status, &*body), (StatusCode::OK, "<html>"));
+        assert_eq!(get("/").0, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn refresh_requires_the_token() {
+        let send = |method, uri| request(context("", Some("secret")), method, uri, CONTENT_TYPE).0;
+        assert_eq!(send(Method::POST, "/refresh"), StatusCode::FORBIDDEN);
+        assert_eq!(
+            send(Method::POST, "/refresh?token=secret"),
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(
+            send(Method::DELETE, "/refresh?token=secret"),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[test]
+    fn corrupt_cache_files_are_rejected() {
+        let file = |parts: &[&[u8]]| parts.join(&CACHE_FILE_SPLITTER);
+        let valid = file(&[CACHE_FILE_MAGIC.as_bytes(), b"0", b"<html>", b"body{}"]);
+        let (generated_at, html, css) = parse_cache_file(&valid).unwrap();
+        assert_eq!(generated_at, Some(UNIX_EPOCH));
+        assert_eq!((&*html, &*css), ("<html>", "body{}"));
+        let truncated = file(&[CACHE_FILE_MAGIC.as_bytes(), b"0", b"<html>"]);
+        assert!(parse_cache_file(&truncated).is_err());
+        assert!(parse_cache_file(b"<html>").is_err());
     }
-    None
 }
@@ -0,0 +1,108 @@
+//! Contains the `--sqlite` export of the counted commits, for running
+//! arbitrary queries over them.
+use chrono::{DateTime, FixedOffset};
+use rusqlite::{params, Connection};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::{log, Commit};
+
+static SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS commits (
+    date TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    project TEXT NOT NULL,
+    path TEXT NOT NULL,
+    author TEXT NOT NULL,
+    lines_changed INTEGER,
+    -- Tells apart the commits with the same time, path and author
+    ordinal INTEGER NOT NULL,
+    UNIQUE (timestamp, path, author, ordinal)
+);
+";
+
+/// Appends the commits into the `commits` table of the database at
+/// `path`, creating the database and the table if needed. The commits
+/// that are already in the table, i.e. with the same time, repository
+/// path and author, are skipped, so the same repositories can be
+/// exported again without counting their commits twice.
+pub fn export(path: &Path, commit_dates: &[(DateTime<FixedOffset>, Commit)]) {
+    match write_commits(path, commit_dates) {
+        Ok(inserted) => log::verbose_println(
+            &format!(
+                "wrote {} commits into {}, {} were already in it",
+                inserted,
+                path.display(),
+                commit_dates.len() - inserted
+            ),
+            false,
+        ),
+        Err(err) => log::error_println(&format!(
+            "error: could not write the commits into {}: {}",
+            path.display(),
+            err
+        )),
+    }
+}
+
+/// Inserts the commits in a single transaction, returning how many of
+/// them weren't in the table yet.
+fn write_commits(
+    path: &Path,
+    commit_dates: &[(DateTime<FixedOffset>, Commit)],
+) -> rusqlite::Result<usize> {
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    let transaction = connection.transaction()?;
+    let mut inserted = 0;
+    {
+        let mut insert = transaction
+            .prepare("INSERT OR IGNORE INTO commits VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+        let mut ordinals: HashMap<(i64, &Path, &str), i64> = HashMap::new();
+        for (date, commit) in commit_dates {
+            let key = (
+                date.timestamp(),
+                commit.project.path.as_path(),
+                &*commit.author,
+            );
+            let ordinal = ordinals.entry(key).or_insert(0);
+            inserted += insert.execute(params![
+                date.to_rfc3339(),
+                date.timestamp(),
+                commit.project.name,
+                commit.project.path.to_string_lossy(),
+                commit.author,
+                commit.lines_changed.map(|lines| lines as i64),
+                *ordinal,
+            ])?;
+            *ordinal += 1;
+        }
+    }
+    transaction.commit()?;
+    Ok(inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::write_commits;
+    use crate::render::tests::commits_on;
+
+    #[test]
+    fn exporting_again_inserts_no_duplicates() {
+        let path = env::temp_dir().join(format!("activity-graph-{}.sqlite", std::process::id()));
+        let _ = fs::remove_file(&path);
+        // The same time, project and author twice, told apart by the
+        // ordinal
+        let commits = commits_on(&[(2021, 3, 1), (2021, 3, 1), (2021, 3, 2)]);
+
+        let first = write_commits(&path, &commits);
+        let second = write_commits(&path, &commits);
+        let _ = fs::remove_file(&path);
+        assert_eq!(first.unwrap(), 3);
+        assert_eq!(second.unwrap(), 0);
+    }
+}
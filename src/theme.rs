@@ -0,0 +1,129 @@
+//! Contains the theme file parsing, and the generation of the css
+//! that applies the theme's colors.
+use serde::Deserialize;
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::log;
+use crate::render::ShadeRamp;
+
+static LIGHT_CSS: &str = include_str!("light.css");
+static DARK_CSS: &str = include_str!("dark.css");
+
+/// The built-in colors of the page and the graph.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorScheme {
+    /// The light or the dark colors, depending on the reader's
+    /// `prefers-color-scheme`.
+    #[default]
+    Auto,
+    Light,
+    Dark,
+}
+
+impl FromStr for ColorScheme {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ColorScheme, String> {
+        match s {
+            "auto" => Ok(ColorScheme::Auto),
+            "light" => Ok(ColorScheme::Light),
+            "dark" => Ok(ColorScheme::Dark),
+            _ => Err(format!("unknown theme: {}", s)),
+        }
+    }
+}
+
+impl ColorScheme {
+    /// Renders the css rules setting the page's background and text
+    /// colors, and the colors of the shade levels.
+    pub fn css(self) -> String {
+        match self {
+            ColorScheme::Light => LIGHT_CSS.to_string(),
+            ColorScheme::Dark => DARK_CSS.to_string(),
+            ColorScheme::Auto => {
+                let dark: String = DARK_CSS
+                    .lines()
+                    .map(|line| match line {
+                        "" => String::from("\n"),
+                        line => format!("    {}\n", line),
+                    })
+                    .collect();
+                format!(
+                    "{}\n@media (prefers-color-scheme: dark) {{\n{}}}\n",
+                    LIGHT_CSS, dark
+                )
+            }
+        }
+    }
+}
+
+/// The colors of the graph, read from a TOML file like this:
+///
+/// ```toml
+/// background = "#000"
+/// text = "#aaa"
+/// levels = ["#131313", "#2e3035", "#414752", "#525f74", "#62789a"]
+/// ```
+///
+/// All of the fields are optional, the missing ones are left to the
+/// built-in stylesheet.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Theme {
+    background: Option<String>,
+    text: Option<String>,
+    /// The colors of the shade levels, from no commits to the most.
+    levels: Option<Vec<String>>,
+}
+
+impl Theme {
+    /// Reads the theme from the file. If it can't be read or parsed,
+    /// the error is printed out and None is returned.
+    pub fn from_file(path: &Path) -> Option<Theme> {
+        let parse_result = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|s| toml::from_str(&s).map_err(|err| err.to_string()));
+        match parse_result {
+            Ok(theme) => Some(theme),
+            Err(err) => {
+                log::error_println(&format!(
+                    "error: could not load the theme file {}: {}",
+                    path.display(),
+                    err
+                ));
+                None
+            }
+        }
+    }
+
+    /// Renders the css rules that apply the theme. These are meant to
+    /// be placed after the built-in css, so that they override it.
+    pub fn css(&self) -> String {
+        let mut result = String::from("/* Theme */\n");
+        if self.background.is_some() || self.text.is_some() {
+            result += "html {";
+            if let Some(background) = &self.background {
+                result += &format!(" background-color: {};", background);
+            }
+            if let Some(text) = &self.text {
+                result += &format!(" color: {};", text);
+            }
+            result += " }\n";
+        }
+        for (level, color) in self
+            .levels
+            .iter()
+            .flatten()
+            .enumerate()
+            .take(ShadeRamp::LEVELS)
+        {
+            result += &format!(
+                ".lvl{0}, .filler-day.lvl{0} {{ background-color: {1}; fill: {1}; }}\n",
+                level, color
+            );
+        }
+        result
+    }
+}